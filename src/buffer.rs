@@ -1,56 +1,185 @@
+use crate::ansitypes::*;
+use crate::event::Action;
 use crate::surface::Coord;
 
-const CSI: &'static str = "\x1b[";
-const OSC: &'static str = "\x1b]";
-
-#[derive(Clone, Copy)]
-pub enum Color {
-    Black,
-    Red,
-    Green,
-    Yellow,
-    Blue,
-    Magenta,
-    Cyan,
-    White,
-    Extended(usize, usize, usize),
-    Default,
-}
-
-#[derive(Clone, Copy)]
-pub enum Formatting {
-    Default,
-    Bright,
-    Underline,
-    NoUnderline,
-    Negative,
-    Positive,
-    ForegroundColor(Color),
-    BrightForegroundColor(Color),
-    BackgroundColor(Color),
-    BrightBackgroundColor(Color),
-}
-
-#[derive(Clone)]
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::ops::Range;
+use unicode_width::UnicodeWidthChar;
+
+/// Lines of scrollback a buffer keeps unless told otherwise.
+pub const DEFAULT_SCROLLBACK_LIMIT: usize = 2000;
+
+/// Graphic rendition a cell is drawn with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Color,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub blink: bool,
+    pub reverse: bool,
+    pub hidden: bool,
+    pub strike: bool,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style {
+            fg: Color::Named(NamedColor::Foreground),
+            bg: Color::Named(NamedColor::Background),
+            bold: false,
+            dim: false,
+            italic: false,
+            underline: false,
+            blink: false,
+            reverse: false,
+            hidden: false,
+            strike: false,
+        }
+    }
+}
+
+impl Style {
+    pub fn apply(&mut self, attr: Attr) {
+        match attr {
+            Attr::Reset => *self = Style::default(),
+            Attr::Bold => self.bold = true,
+            Attr::Dim => self.dim = true,
+            Attr::Italic => self.italic = true,
+            Attr::Underscore => self.underline = true,
+            Attr::BlinkSlow | Attr::BlinkFast => self.blink = true,
+            Attr::Reverse => self.reverse = true,
+            Attr::Hidden => self.hidden = true,
+            Attr::Strike => self.strike = true,
+            Attr::CancelBold => self.bold = false,
+            Attr::CancelBoldDim => {
+                self.bold = false;
+                self.dim = false;
+            }
+            Attr::CancelItalic => self.italic = false,
+            Attr::CancelUnderline => self.underline = false,
+            Attr::CancelBlink => self.blink = false,
+            Attr::CancelReverse => self.reverse = false,
+            Attr::CancelHidden => self.hidden = false,
+            Attr::CancelStrike => self.strike = false,
+            Attr::Foreground(color) => self.fg = color,
+            Attr::Background(color) => self.bg = color,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Cell {
     c: Vec<CellContents>,
+    pub style: Style,
 }
 
 impl Default for Cell {
     fn default() -> Cell {
         Cell {
             c: vec![CellContents::Empty; 1],
+            style: Style::default(),
+        }
+    }
+}
+
+impl Cell {
+    pub fn new(c: char, style: Style) -> Cell {
+        Cell {
+            c: vec![CellContents::Character(c)],
+            style,
+        }
+    }
+
+    /// A blank cell that keeps the background of the given style, as erasing does.
+    pub fn blank(style: Style) -> Cell {
+        Cell {
+            c: vec![CellContents::Empty],
+            style: Style {
+                bg: style.bg,
+                ..Style::default()
+            },
+        }
+    }
+
+    /// The right half of the wide character in the cell before.
+    pub fn spacer(style: Style) -> Cell {
+        Cell {
+            c: vec![CellContents::Spacer],
+            style,
+        }
+    }
+
+    pub fn is_spacer(&self) -> bool {
+        self.c.first() == Some(&CellContents::Spacer)
+    }
+
+    /// Whether the character in the cell takes up two columns, this one and a spacer.
+    pub fn is_wide(&self) -> bool {
+        match self.c.first() {
+            Some(CellContents::Character(c)) => c.width() == Some(2),
+            _ => false,
+        }
+    }
+
+    /// Adds a combining character, or one joined to the rest of the cell, after what is there.
+    fn push(&mut self, c: char) {
+        self.c.push(CellContents::Character(c));
+    }
+
+    /// Whether the next character printed joins onto this cell.
+    fn joining(&self) -> bool {
+        self.c.last() == Some(&CellContents::Character(ZERO_WIDTH_JOINER))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.c.iter().all(|c| match c {
+            CellContents::Empty => true,
+            _ => false,
+        })
+    }
+
+    pub fn contents(&self) -> &[CellContents] {
+        &self.c
+    }
+
+    /// Characters in the cell, or a space if it is empty.
+    ///
+    /// A spacer has no text, the wide character before it already covers its column.
+    pub fn text(&self) -> String {
+        if self.is_spacer() {
+            return String::new();
+        }
+
+        let text: String = self
+            .c
+            .iter()
+            .filter_map(|c| match c {
+                CellContents::Character(c) => Some(*c),
+                CellContents::Empty | CellContents::Spacer => None,
+            })
+            .collect();
+        if text.is_empty() {
+            String::from(" ")
+        } else {
+            text
         }
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum CellContents {
     Empty,
     Character(char),
-    Format(Formatting),
+    /// Stands in for the right half of a wide character
+    Spacer,
 }
 
+const ZERO_WIDTH_JOINER: char = '\u{200d}';
+
 pub enum CursorDirection {
     Forward,
     Backward,
@@ -59,10 +188,12 @@ pub enum CursorDirection {
     Position(usize, usize),
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Row {
     inner: Vec<Cell>,
     width: usize,
+    /// The line continues on the next row because it was wrapped, not broken by a newline.
+    wrapped: bool,
 }
 
 impl Row {
@@ -70,60 +201,601 @@ impl Row {
         Row {
             inner: vec![Cell::default(); dimensions.x],
             width: dimensions.x,
+            wrapped: false,
         }
     }
 
     fn clear(&mut self, c: Cell) {
-        self.inner = vec![Cell::default(); self.width];
+        self.inner = vec![c; self.width];
+        self.wrapped = false;
     }
 
+    /// Writes a cell, and a spacer after it if it is wide.
     fn write(&mut self, c: Cell, cursor: usize) {
-        if cursor < self.inner.len() {
-            // cursor is at most len - 1
-            self.inner[cursor] = c;
-        } else {
-            // cursor is at least len here.
-            for _ in self.inner.len()..cursor {
-                self.inner.push(Cell::default());
+        if cursor >= self.inner.len() {
+            return;
+        }
+
+        self.split_wide(cursor);
+        if c.is_wide() && cursor + 1 < self.inner.len() {
+            self.split_wide(cursor + 1);
+            self.inner[cursor + 1] = Cell::spacer(c.style);
+        }
+        self.inner[cursor] = c;
+    }
+
+    /// Blanks the other half of a wide character at `x`, before `x` is written over.
+    fn split_wide(&mut self, x: usize) {
+        if self.inner[x].is_spacer() && x > 0 {
+            let style = self.inner[x - 1].style;
+            self.inner[x - 1] = Cell::blank(style);
+        }
+        if self.inner[x].is_wide() {
+            if let Some(next) = self.inner.get_mut(x + 1).filter(|c| c.is_spacer()) {
+                *next = Cell::blank(next.style);
+            }
+        }
+    }
+
+    /// Blanks the halves of wide characters that were separated by shifting or cutting the row.
+    fn fix_wide(&mut self) {
+        for x in 0..self.inner.len() {
+            let whole = if self.inner[x].is_wide() {
+                self.inner.get(x + 1).map_or(false, |c| c.is_spacer())
+            } else if self.inner[x].is_spacer() {
+                x > 0 && self.inner[x - 1].is_wide()
+            } else {
+                true
+            };
+            if !whole {
+                let style = self.inner[x].style;
+                self.inner[x] = Cell::blank(style);
             }
-            self.inner.push(c);
         }
     }
+
+    fn resize(&mut self, width: usize) {
+        self.inner.resize(width, Cell::default());
+        self.width = width;
+        self.fix_wide();
+    }
+
+    pub fn cells(&self) -> &[Cell] {
+        &self.inner
+    }
+
+    pub fn wrapped(&self) -> bool {
+        self.wrapped
+    }
+
+    /// The text of the row with trailing blanks removed.
+    pub fn text(&self) -> String {
+        let text: String = self.inner.iter().map(|c| c.text()).collect();
+        text.trim_end().to_owned()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct SavedCursor {
+    cursor: Coord,
+    style: Style,
+    charsets: [StandardCharset; 4],
+    active_charset: CharsetIndex,
 }
 
 pub struct Buffer {
     buffer: Vec<Row>,
+    /// The screen that isn't on show, the primary screen while the alternate screen is up
+    other: Vec<Row>,
+    alternate: bool,
     cursor: Coord,
     dimensions: Coord,
+    style: Style,
+    saved_cursor: Option<SavedCursor>,
+    /// The saved cursor of the screen that isn't on show, each screen keeps its own
+    other_saved_cursor: Option<SavedCursor>,
+    /// Set when a character was printed in the last column, the next one wraps.
+    wrap_pending: bool,
+    /// The rows between the top and bottom margins, which scroll on their own
+    scroll_region: Range<usize>,
+    /// Whether there is a tab stop at each column
+    tabs: Vec<bool>,
+    /// The character sets designated as G0 to G3
+    charsets: [StandardCharset; 4],
+    active_charset: CharsetIndex,
+    /// The set the next character printed is in, from a single shift
+    single_shift: Option<CharsetIndex>,
+    modes: Vec<Mode>,
+    title: Option<String>,
+    /// Lines that scrolled off the top of the screen, oldest first
+    scrollback: VecDeque<Row>,
+    scrollback_limit: usize,
+    /// How many lines back into the scrollback the pane is being viewed, 0 is the live screen
+    view_offset: usize,
+    /// Whether the view stays on the same lines even when it is on the live screen
+    view_frozen: bool,
+    /// Lines dropped off the front of the scrollback since the buffer was made
+    trimmed: usize,
 }
 
 impl Buffer {
-    fn new(dimensions: Coord) -> Buffer {
+    pub fn new(dimensions: Coord) -> Buffer {
         Buffer {
             buffer: vec![Row::new(dimensions); dimensions.y],
+            other: vec![Row::new(dimensions); dimensions.y],
+            alternate: false,
             cursor: (0, 0).into(),
             dimensions: dimensions,
+            style: Style::default(),
+            saved_cursor: None,
+            other_saved_cursor: None,
+            wrap_pending: false,
+            scroll_region: 0..dimensions.y,
+            tabs: default_tabs(dimensions.x),
+            charsets: [StandardCharset::Ascii; 4],
+            active_charset: CharsetIndex::G0,
+            single_shift: None,
+            modes: vec![Mode::LineWrap, Mode::ShowCursor],
+            title: None,
+            scrollback: VecDeque::new(),
+            scrollback_limit: DEFAULT_SCROLLBACK_LIMIT,
+            view_offset: 0,
+            view_frozen: false,
+            trimmed: 0,
         }
     }
 
-    fn push_cell(&mut self, c: Cell) {
+    /// Applies an action parsed from the output of the console.
+    pub fn perform(&mut self, action: &Action) {
+        if self.dimensions.x == 0 || self.dimensions.y == 0 {
+            return;
+        }
+
+        match *action {
+            Action::PtyOutput(_, c) => self.input(c),
+            Action::PtyInsertBlank(_, count) => self.insert_blank(count.0),
+            Action::PtyPutTabs(_, count) => self.put_tab(count),
+            Action::PtyCursorMoveForwardTabs(_, count) => self.put_tab(count.0 as i64),
+            Action::PtyCursorMoveBackwardsTabs(_, count) => self.back_tab(count.0),
+            Action::PtySetHorizontalTabstop(_) => self.set_tab(),
+            Action::PtyClearTabs(_, mode) => self.clear_tabs(mode),
+            Action::PtyConfigureCharset(_, index, charset) => {
+                self.charsets[index as usize] = charset
+            }
+            Action::PtySetActiveCharset(_, index) => self.active_charset = index,
+            Action::PtySingleShift(_, index) => self.single_shift = Some(index),
+            Action::PtyBackspace(_) => self.backspace(),
+            Action::PtyCarriageReturn(_) => self.carriage_return(),
+            Action::PtyLineFeed(_) => self.linefeed(),
+            Action::PtyReverseIndex(_) => self.reverse_index(),
+            Action::PtyNewline(_) => self.newline(),
+            Action::PtyInsertBlankLines(_, count) => self.insert_blank_lines(count.0),
+            Action::PtyDeleteLines(_, count) => self.delete_lines(count.0),
+            Action::PtyEraseCharacters(_, count) => self.erase_chars(count.0),
+            Action::PtyDeleteCharacters(_, count) => self.delete_chars(count.0),
+            Action::PtyClearLine(_, mode) => self.clear_line(mode),
+            Action::PtyClearScreen(_, mode) => self.clear_screen(mode),
+            Action::PtyVtScrollUp(_, count) => self.scroll_up(count.0),
+            Action::PtyVtScrollDown(_, count) => self.scroll_down(count.0),
+            Action::PtySetScrollingRegion(_, ref region) => {
+                self.set_scrolling_region(region.start.0, region.end.0)
+            }
+            Action::PtyReset(_) => self.reset(),
+            Action::PtyCursorGoto(_, line, column) => self.goto(line.0, column.0),
+            Action::PtyCursorGotoLine(_, line) => self.goto_line(line.0),
+            Action::PtyCursorGotoColumn(_, column) => self.goto_column(column.0),
+            Action::PtyCursorMoveUp(_, count) => self.move_up(count.0),
+            Action::PtyCursorMoveDown(_, count) => self.move_down(count.0),
+            Action::PtyCursorMoveForward(_, count) => self.move_forward(count.0),
+            Action::PtyCursorMoveBackward(_, count) => self.move_backward(count.0),
+            Action::PtyCursorMoveUpAndCarriageReturn(_, count) => {
+                self.move_up(count.0);
+                self.carriage_return();
+            }
+            Action::PtyCursorMoveDownAndCarriageReturn(_, count) => {
+                self.move_down(count.0);
+                self.carriage_return();
+            }
+            Action::PtyCursorSavePosition(_) => self.save_cursor(),
+            Action::PtyCursorRestorePosition(_) => self.restore_cursor(),
+            Action::PtyTerminalAttribute(_, attr) => self.terminal_attribute(attr),
+            Action::PtySetMode(_, mode) => self.set_mode(mode),
+            Action::PtyUnsetMode(_, mode) => self.unset_mode(mode),
+            Action::PtySetTitle(_, ref title) => self.title = Some(title.clone()),
+            _ => (),
+        }
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_ref().map(|t| t.as_str())
+    }
+
+    pub fn rows(&self) -> &[Row] {
+        &self.buffer
+    }
+
+    pub fn cursor(&self) -> &Coord {
+        &self.cursor
+    }
+
+    pub fn dimensions(&self) -> &Coord {
+        &self.dimensions
+    }
+
+    pub fn scrollback(&self) -> &VecDeque<Row> {
+        &self.scrollback
+    }
+
+    /// Sets how many lines of scrollback are kept, dropping the oldest lines over the limit.
+    pub fn set_scrollback_limit(&mut self, limit: usize) {
+        self.scrollback_limit = limit;
+        while self.scrollback.len() > limit {
+            self.scrollback.pop_front();
+            self.trimmed += 1;
+        }
+        self.view_offset = min(self.view_offset, self.scrollback.len());
+    }
+
+    fn push_scrollback(&mut self, row: Row) {
+        if self.scrollback_limit == 0 {
+            return;
+        }
+
+        self.scrollback.push_back(row);
+        if self.scrollback.len() > self.scrollback_limit {
+            self.scrollback.pop_front();
+            self.trimmed += 1;
+        }
+
+        // Keep the view on the same lines while new output scrolls the screen.
+        if self.view_offset > 0 || self.view_frozen {
+            self.view_offset = min(self.view_offset + 1, self.scrollback.len());
+        }
+    }
+
+    /// Number of lines in the scrollback and on the screen together.
+    pub fn line_count(&self) -> usize {
+        self.scrollback.len() + self.buffer.len()
+    }
+
+    /// A line counting from the oldest line of the scrollback, screen lines come last.
+    pub fn line(&self, n: usize) -> Option<&Row> {
+        if n < self.scrollback.len() {
+            self.scrollback.get(n)
+        } else {
+            self.buffer.get(n - self.scrollback.len())
+        }
+    }
+
+    /// Number of lines dropped off the front of the scrollback so far.
+    ///
+    /// Adding it to a line number gives a position that stays put as the scrollback is trimmed.
+    pub fn first_line(&self) -> usize {
+        self.trimmed
+    }
+
+    pub fn view_offset(&self) -> usize {
+        self.view_offset
+    }
+
+    /// Number of the first line on view, counting like `line`.
+    pub fn view_start(&self) -> usize {
+        self.line_count() - self.buffer.len() - self.view_offset
+    }
+
+    /// Keeps the view on the lines it shows while output arrives, or lets it follow the screen again.
+    pub fn freeze_view(&mut self, frozen: bool) {
+        self.view_frozen = frozen;
+    }
+
+    /// Scrolls the view back into the scrollback by `lines`, or towards the live screen if negative.
+    pub fn scroll_view(&mut self, lines: isize) {
+        let offset = self.view_offset as isize + lines;
+        self.view_offset = min(offset.max(0) as usize, self.scrollback.len());
+    }
+
+    /// Goes back to viewing the live screen, returning whether the view moved.
+    pub fn reset_view(&mut self) -> bool {
+        let moved = self.view_offset != 0;
+        self.view_offset = 0;
+        moved
+    }
+
+    /// The rows on view, which are the screen rows unless the view is scrolled back.
+    pub fn visible_rows(&self) -> Vec<&Row> {
+        let start = self.view_start();
+        (start..start + self.buffer.len())
+            .filter_map(|n| self.line(n))
+            .collect()
+    }
+
+    pub fn mode(&self, mode: Mode) -> bool {
+        self.modes.contains(&mode)
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        match mode {
+            Mode::SwapScreen | Mode::SwapScreenAndClear => self.swap_screen(true),
+            Mode::SaveRestoreCursor => self.save_cursor(),
+            Mode::SwapScreenAndSetRestoreCursor => {
+                self.save_cursor();
+                self.swap_screen(true);
+                self.clear_grid();
+            }
+            _ => (),
+        }
+
+        if !self.mode(mode) {
+            self.modes.push(mode);
+        }
+
+        if mode == Mode::Origin {
+            self.goto(0, 0);
+        }
+    }
+
+    pub fn unset_mode(&mut self, mode: Mode) {
+        match mode {
+            Mode::SwapScreen => self.swap_screen(false),
+            Mode::SwapScreenAndClear => {
+                if self.alternate {
+                    self.clear_grid();
+                }
+                self.swap_screen(false);
+            }
+            Mode::SaveRestoreCursor => self.restore_cursor(),
+            Mode::SwapScreenAndSetRestoreCursor => {
+                self.swap_screen(false);
+                self.restore_cursor();
+            }
+            _ => (),
+        }
+
+        self.modes.retain(|m| *m != mode);
+
+        if mode == Mode::Origin {
+            self.goto(0, 0);
+        }
+    }
+
+    /// Whether the alternate screen is on show, which full screen programs draw on.
+    pub fn alternate_screen(&self) -> bool {
+        self.alternate
+    }
+
+    /// Shows the alternate screen, or the primary screen again, if it isn't already showing.
+    fn swap_screen(&mut self, alternate: bool) {
+        if self.alternate != alternate {
+            std::mem::swap(&mut self.buffer, &mut self.other);
+            std::mem::swap(&mut self.saved_cursor, &mut self.other_saved_cursor);
+            self.alternate = alternate;
+            self.wrap_pending = false;
+        }
+    }
+
+    /// Blanks every row of the screen on show, with the default style.
+    fn clear_grid(&mut self) {
+        for row in &mut self.buffer {
+            row.clear(Cell::default());
+        }
+    }
+
+    /// Prints a character at the cursor with the current style.
+    pub fn input(&mut self, c: char) {
+        if self.dimensions.x == 0 || self.dimensions.y == 0 {
+            return;
+        }
+
+        let charset = self.single_shift.take().unwrap_or(self.active_charset);
+        let c = self.charsets[charset as usize].map(c);
+        let width = match c.width() {
+            Some(width) => width,
+            None => return,
+        };
+
+        // Combining marks, and whatever follows a zero width joiner, go with the character before.
+        if let Some(x) = self.previous_column() {
+            let cell = &mut self.buffer[self.cursor.y].inner[x];
+            if width == 0 || cell.joining() {
+                cell.push(c);
+                return;
+            }
+        }
+        if width == 0 || width > self.dimensions.x {
+            return;
+        }
+
+        if self.cursor.x + width > self.dimensions.x {
+            if self.mode(Mode::LineWrap) {
+                // A wide character in the last column is moved onto the next line whole.
+                if !self.wrap_pending {
+                    let blank = Cell::blank(self.style);
+                    self.buffer[self.cursor.y].write(blank, self.cursor.x);
+                    self.wrap_pending = true;
+                }
+            } else {
+                self.cursor.x = self.dimensions.x - width;
+            }
+        }
+
+        if self.wrap_pending {
+            self.wrap_pending = false;
+            self.buffer[self.cursor.y].wrapped = true;
+            self.carriage_return();
+            self.linefeed();
+        }
+
+        if self.mode(Mode::Insert) {
+            self.insert_blank(width);
+        }
+
+        let cell = Cell::new(c, self.style);
+        self.push_cell(cell, width);
+    }
+
+    /// The column of the last character printed on the cursor's row, if it is still there.
+    fn previous_column(&self) -> Option<usize> {
+        let x = if self.wrap_pending {
+            self.cursor.x
+        } else {
+            self.cursor.x.checked_sub(1)?
+        };
+        let row = &self.buffer[self.cursor.y];
+        if row.inner[x].is_spacer() && x > 0 {
+            Some(x - 1)
+        } else {
+            Some(x)
+        }
+    }
+
+    fn push_cell(&mut self, c: Cell, width: usize) {
         let row = &mut self.buffer[self.cursor.y];
         row.write(c, self.cursor.x);
-        self.cursor.x += 1;
+        if self.cursor.x + width < self.dimensions.x {
+            self.cursor.x += width;
+        } else {
+            self.cursor.x = self.dimensions.x - 1;
+            if self.mode(Mode::LineWrap) {
+                self.wrap_pending = true;
+            }
+        }
+    }
+
+    fn push_newline(&mut self) {
+        self.cursor.x = 0;
+        self.linefeed();
     }
 
-    fn push_newline(&mut self, c: Cell) {
+    pub fn carriage_return(&mut self) {
         self.cursor.x = 0;
-        self.cursor.y += 1;
-        if self.cursor.y > self.buffer.len() {
-            self.buffer.push(Row::new(self.dimensions));
+        self.wrap_pending = false;
+    }
+
+    /// Moves down a line, scrolling the region if the cursor is on the bottom margin.
+    pub fn linefeed(&mut self) {
+        self.wrap_pending = false;
+        if self.cursor.y + 1 == self.scroll_region.end {
+            self.scroll_up(1);
+        } else if self.cursor.y + 1 < self.dimensions.y {
+            self.cursor.y += 1;
+        }
+    }
+
+    /// Moves up a line, scrolling the region down if the cursor is on the top margin.
+    pub fn reverse_index(&mut self) {
+        self.wrap_pending = false;
+        if self.cursor.y == self.scroll_region.start {
+            self.scroll_down(1);
+        } else if self.cursor.y > 0 {
+            self.cursor.y -= 1;
+        }
+    }
+
+    pub fn newline(&mut self) {
+        self.push_newline();
+    }
+
+    pub fn backspace(&mut self) {
+        self.wrap_pending = false;
+        self.set_cursor(CursorDirection::Backward);
+    }
+
+    /// Moves to the next tab stop, or the last column if there are no more.
+    pub fn put_tab(&mut self, count: i64) {
+        self.wrap_pending = false;
+        let last = self.dimensions.x.saturating_sub(1);
+        for _ in 0..count {
+            self.cursor.x = (self.cursor.x + 1..last)
+                .find(|&x| self.tabs[x])
+                .unwrap_or(last);
+        }
+    }
+
+    /// Moves to the previous tab stop, or the first column if there are no more.
+    pub fn back_tab(&mut self, count: usize) {
+        self.wrap_pending = false;
+        for _ in 0..count {
+            self.cursor.x = (0..self.cursor.x).rev().find(|&x| self.tabs[x]).unwrap_or(0);
+        }
+    }
+
+    /// Sets a tab stop at the cursor.
+    pub fn set_tab(&mut self) {
+        self.tabs[self.cursor.x] = true;
+    }
+
+    pub fn clear_tabs(&mut self, mode: TabulationClearMode) {
+        match mode {
+            TabulationClearMode::Current => self.tabs[self.cursor.x] = false,
+            TabulationClearMode::All => {
+                for tab in &mut self.tabs {
+                    *tab = false;
+                }
+            }
+        }
+    }
+
+    /// Moves the contents of the scrolling region up, new blank lines appear at the bottom.
+    ///
+    /// Lines scrolled off the top of the screen go into the scrollback, unless it is the
+    /// alternate screen.
+    pub fn scroll_up(&mut self, lines: usize) {
+        let region = self.scroll_region.clone();
+        let scrolled = self.scroll_rows(region.start, region.end, lines);
+        if region.start == 0 && !self.alternate {
+            for row in scrolled {
+                self.push_scrollback(row);
+            }
+        }
+    }
+
+    /// Moves the contents of the scrolling region down, new blank lines appear at the top.
+    pub fn scroll_down(&mut self, lines: usize) {
+        let region = self.scroll_region.clone();
+        self.scroll_rows_down(region.start, region.end, lines);
+    }
+
+    /// Removes lines from the top of the rows `start..end`, filling the bottom with blank lines.
+    fn scroll_rows(&mut self, start: usize, end: usize, lines: usize) -> Vec<Row> {
+        let lines = min(lines, end - start);
+        let blank = self.blank_row();
+        let scrolled = self.buffer.drain(start..start + lines).collect();
+        for _ in 0..lines {
+            self.buffer.insert(end - lines, blank.clone());
+        }
+        scrolled
+    }
+
+    /// Removes lines from the bottom of the rows `start..end`, filling the top with blank lines.
+    fn scroll_rows_down(&mut self, start: usize, end: usize, lines: usize) {
+        let lines = min(lines, end - start);
+        let blank = self.blank_row();
+        self.buffer.drain(end - lines..end);
+        for _ in 0..lines {
+            self.buffer.insert(start, blank.clone());
+        }
+    }
+
+    /// Sets the top and bottom margins and homes the cursor, regions under two lines are ignored.
+    pub fn set_scrolling_region(&mut self, top: usize, bottom: usize) {
+        let bottom = min(bottom, self.dimensions.y);
+        if top + 1 >= bottom {
+            return;
         }
+        self.scroll_region = top..bottom;
+        self.goto(0, 0);
+    }
+
+    fn blank_row(&self) -> Row {
+        let mut row = Row::new(self.dimensions);
+        row.clear(Cell::blank(self.style));
+        row
     }
 
     fn set_cursor(&mut self, c: CursorDirection) {
         match c {
             CursorDirection::Forward => {
-                if self.cursor.x < self.dimensions.x {
+                if self.cursor.x + 1 < self.dimensions.x {
                     self.cursor.x += 1;
                 }
             }
@@ -132,28 +804,635 @@ impl Buffer {
                     self.cursor.x -= 1;
                 }
             }
+            // The cursor stops at the margins when it starts out inside the region.
             CursorDirection::Up => {
-                if self.cursor.y > 0 {
+                if self.cursor.y > 0 && self.cursor.y != self.scroll_region.start {
                     self.cursor.y -= 1;
                 }
             }
             CursorDirection::Down => {
-                if self.cursor.y < self.buffer.len() {
+                let y = self.cursor.y + 1;
+                if y < self.buffer.len() && y != self.scroll_region.end {
                     self.cursor.y += 1;
                 }
             }
             CursorDirection::Position(x, y) => {
-                if y >= 0 && y < self.buffer.len() {
-                    self.cursor.y = y;
+                self.cursor.y = min(y, self.buffer.len().saturating_sub(1));
+                self.cursor.x = min(x, self.dimensions.x.saturating_sub(1));
+            }
+        }
+    }
+
+    /// Moves the cursor, counting lines from the top margin in origin mode.
+    pub fn goto(&mut self, line: usize, column: usize) {
+        self.wrap_pending = false;
+        let line = if self.mode(Mode::Origin) {
+            min(self.scroll_region.start + line, self.scroll_region.end - 1)
+        } else {
+            line
+        };
+        self.set_cursor(CursorDirection::Position(column, line));
+    }
+
+    pub fn goto_line(&mut self, line: usize) {
+        let column = self.cursor.x;
+        self.goto(line, column);
+    }
+
+    pub fn goto_column(&mut self, column: usize) {
+        self.wrap_pending = false;
+        let line = self.cursor.y;
+        self.set_cursor(CursorDirection::Position(column, line));
+    }
+
+    pub fn move_up(&mut self, lines: usize) {
+        self.wrap_pending = false;
+        for _ in 0..lines {
+            self.set_cursor(CursorDirection::Up);
+        }
+    }
+
+    pub fn move_down(&mut self, lines: usize) {
+        self.wrap_pending = false;
+        for _ in 0..lines {
+            self.set_cursor(CursorDirection::Down);
+        }
+    }
+
+    pub fn move_forward(&mut self, columns: usize) {
+        self.wrap_pending = false;
+        for _ in 0..columns {
+            self.set_cursor(CursorDirection::Forward);
+        }
+    }
+
+    pub fn move_backward(&mut self, columns: usize) {
+        self.wrap_pending = false;
+        for _ in 0..columns {
+            self.set_cursor(CursorDirection::Backward);
+        }
+    }
+
+    /// Saves the cursor along with the style and character sets.
+    pub fn save_cursor(&mut self) {
+        self.saved_cursor = Some(SavedCursor {
+            cursor: self.cursor,
+            style: self.style,
+            charsets: self.charsets,
+            active_charset: self.active_charset,
+        });
+    }
+
+    pub fn restore_cursor(&mut self) {
+        let saved = self.saved_cursor.unwrap_or(SavedCursor {
+            cursor: (0, 0).into(),
+            style: Style::default(),
+            charsets: [StandardCharset::Ascii; 4],
+            active_charset: CharsetIndex::G0,
+        });
+        self.style = saved.style;
+        self.charsets = saved.charsets;
+        self.active_charset = saved.active_charset;
+        self.wrap_pending = false;
+        self.set_cursor(CursorDirection::Position(saved.cursor.x, saved.cursor.y));
+    }
+
+    pub fn terminal_attribute(&mut self, attr: Attr) {
+        self.style.apply(attr);
+    }
+
+    pub fn clear_line(&mut self, mode: LineClearMode) {
+        let blank = Cell::blank(self.style);
+        let x = self.cursor.x;
+        let row = &mut self.buffer[self.cursor.y];
+        let range = match mode {
+            LineClearMode::Right => x..row.width,
+            LineClearMode::Left => 0..min(x + 1, row.width),
+            LineClearMode::All => 0..row.width,
+        };
+        for cell in &mut row.inner[range] {
+            *cell = blank.clone();
+        }
+        row.fix_wide();
+    }
+
+    pub fn clear_screen(&mut self, mode: ClearMode) {
+        let blank = Cell::blank(self.style);
+        match mode {
+            ClearMode::Below => {
+                self.clear_line(LineClearMode::Right);
+                for row in &mut self.buffer[self.cursor.y + 1..] {
+                    row.clear(blank.clone());
                 }
-                if x >= 0 && x < self.dimensions.x {
-                    self.cursor.x = x;
+            }
+            ClearMode::Above => {
+                self.clear_line(LineClearMode::Left);
+                for row in &mut self.buffer[..self.cursor.y] {
+                    row.clear(blank.clone());
+                }
+            }
+            ClearMode::All => {
+                for row in &mut self.buffer {
+                    row.clear(blank.clone());
                 }
             }
+            ClearMode::Saved => {
+                self.trimmed += self.scrollback.len();
+                self.scrollback.clear();
+                self.view_offset = 0;
+            }
+        }
+    }
+
+    /// Blanks characters from the cursor without moving anything.
+    pub fn erase_chars(&mut self, count: usize) {
+        let blank = Cell::blank(self.style);
+        let x = self.cursor.x;
+        let row = &mut self.buffer[self.cursor.y];
+        let end = min(x + count, row.width);
+        for cell in &mut row.inner[x..end] {
+            *cell = blank.clone();
+        }
+        row.fix_wide();
+    }
+
+    /// Shifts the rest of the line right, inserting blanks at the cursor.
+    pub fn insert_blank(&mut self, count: usize) {
+        let blank = Cell::blank(self.style);
+        let x = self.cursor.x;
+        let row = &mut self.buffer[self.cursor.y];
+        let count = min(count, row.width - x);
+        for _ in 0..count {
+            row.inner.insert(x, blank.clone());
+        }
+        row.inner.truncate(row.width);
+        row.fix_wide();
+    }
+
+    /// Removes characters at the cursor, shifting the rest of the line left.
+    pub fn delete_chars(&mut self, count: usize) {
+        let blank = Cell::blank(self.style);
+        let x = self.cursor.x;
+        let row = &mut self.buffer[self.cursor.y];
+        let count = min(count, row.width - x);
+        row.inner.drain(x..x + count);
+        for _ in 0..count {
+            row.inner.push(blank.clone());
+        }
+        row.fix_wide();
+    }
+
+    /// Inserts blank lines at the cursor, pushing the lines below down and off the bottom margin.
+    ///
+    /// Nothing happens when the cursor is outside the scrolling region.
+    pub fn insert_blank_lines(&mut self, count: usize) {
+        let y = self.cursor.y;
+        if !self.scroll_region.contains(&y) {
+            return;
+        }
+        let end = self.scroll_region.end;
+        self.scroll_rows_down(y, end, count);
+        self.cursor.x = 0;
+    }
+
+    /// Deletes lines at the cursor, pulling the lines above the bottom margin up.
+    ///
+    /// Nothing happens when the cursor is outside the scrolling region.
+    pub fn delete_lines(&mut self, count: usize) {
+        let y = self.cursor.y;
+        if !self.scroll_region.contains(&y) {
+            return;
+        }
+        let end = self.scroll_region.end;
+        self.scroll_rows(y, end, count);
+        self.cursor.x = 0;
+    }
+
+    pub fn resize(&mut self, dimensions: Coord) {
+        let scrolled = resize_grid(&mut self.buffer, dimensions, self.cursor.y);
+        self.cursor.y -= scrolled.len();
+        if let Some(saved) = self.saved_cursor.as_mut() {
+            saved.cursor.y = saved.cursor.y.saturating_sub(scrolled.len());
         }
+
+        // The primary screen keeps the lines around its cursor, which was saved if it is hidden.
+        let primary = if self.alternate {
+            let saved = self.other_saved_cursor.as_mut();
+            let cursor = saved.as_ref().map_or(self.other.len(), |s| s.cursor.y);
+            let scrolled = resize_grid(&mut self.other, dimensions, cursor);
+            if let Some(saved) = saved {
+                saved.cursor.y -= scrolled.len();
+            }
+            scrolled
+        } else {
+            resize_grid(&mut self.other, dimensions, 0);
+            scrolled
+        };
+        for row in primary {
+            self.push_scrollback(row);
+        }
+
+        self.dimensions = dimensions;
+        self.scroll_region = 0..dimensions.y;
+        self.tabs = default_tabs(dimensions.x);
+        self.wrap_pending = false;
+        self.cursor.x = min(self.cursor.x, dimensions.x.saturating_sub(1));
+        self.cursor.y = min(self.cursor.y, dimensions.y.saturating_sub(1));
+    }
+
+    /// Resets the terminal state and shows the primary screen, the scrollback is kept.
+    pub fn reset(&mut self) {
+        let mut buffer = Buffer::new(self.dimensions);
+        buffer.scrollback = std::mem::take(&mut self.scrollback);
+        buffer.scrollback_limit = self.scrollback_limit;
+        buffer.view_offset = self.view_offset;
+        buffer.view_frozen = self.view_frozen;
+        buffer.trimmed = self.trimmed;
+        *self = buffer;
+    }
+
+    /// The visible text of the screen, one line per row.
+    pub fn raw(&self) -> String {
+        self.buffer
+            .iter()
+            .map(|r| r.text())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Tab stops every 8 columns, for a screen `width` columns wide.
+fn default_tabs(width: usize) -> Vec<bool> {
+    (0..width).map(|x| x > 0 && x % 8 == 0).collect()
+}
+
+/// Resizes the rows of a screen, returning the rows dropped off the top when it gets shorter.
+///
+/// Rows are dropped from the top only as far as the line with the cursor, then from the bottom.
+fn resize_grid(rows: &mut Vec<Row>, dimensions: Coord, cursor: usize) -> Vec<Row> {
+    for row in rows.iter_mut() {
+        row.resize(dimensions.x);
+    }
+
+    if dimensions.y < rows.len() {
+        let from_top = min(rows.len() - dimensions.y, cursor);
+        let scrolled = rows.drain(0..from_top).collect();
+        rows.truncate(dimensions.y);
+        scrolled
+    } else {
+        while rows.len() < dimensions.y {
+            rows.push(Row::new(dimensions));
+        }
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::parse;
+
+    fn feed(buffer: &mut Buffer, bytes: &[u8]) {
+        for action in parse(bytes) {
+            buffer.perform(&action);
+        }
+    }
+
+    /// The text of every line, scrollback first.
+    fn lines(buffer: &Buffer) -> Vec<String> {
+        (0..buffer.line_count())
+            .map(|n| buffer.line(n).unwrap().text())
+            .collect()
+    }
+
+    fn visible(buffer: &Buffer) -> Vec<String> {
+        buffer.visible_rows().iter().map(|r| r.text()).collect()
+    }
+
+    #[test]
+    fn scrollback_keeps_up_to_limit() {
+        let mut buffer = Buffer::new(Coord { x: 10, y: 3 });
+        buffer.set_scrollback_limit(2);
+        feed(&mut buffer, b"1\r\n2\r\n3\r\n4\r\n5\r\n6");
+        assert_eq!(lines(&buffer), ["2", "3", "4", "5", "6"]);
+        assert_eq!(buffer.first_line(), 1);
+
+        buffer.set_scrollback_limit(0);
+        feed(&mut buffer, b"\r\n7");
+        assert_eq!(lines(&buffer), ["5", "6", "7"]);
+    }
+
+    #[test]
+    fn lowering_limit_trims_oldest_lines() {
+        let mut buffer = Buffer::new(Coord { x: 10, y: 2 });
+        feed(&mut buffer, b"1\r\n2\r\n3\r\n4\r\n5");
+        buffer.scroll_view(3);
+        assert_eq!(visible(&buffer), ["1", "2"]);
+
+        buffer.set_scrollback_limit(1);
+        assert_eq!(lines(&buffer), ["3", "4", "5"]);
+        assert_eq!(buffer.first_line(), 2);
+        assert_eq!(buffer.view_offset(), 1);
+        assert_eq!(visible(&buffer), ["3", "4"]);
+    }
+
+    #[test]
+    fn frozen_view_stays_put_while_output_arrives() {
+        let mut buffer = Buffer::new(Coord { x: 10, y: 2 });
+        feed(&mut buffer, b"a\r\nb\r\nc");
+        buffer.freeze_view(true);
+        feed(&mut buffer, b"\r\nd\r\ne");
+        assert_eq!(visible(&buffer), ["b", "c"]);
+        assert_eq!(buffer.view_offset(), 2);
+
+        buffer.freeze_view(false);
+        feed(&mut buffer, b"\r\nf");
+        assert_eq!(visible(&buffer), ["b", "c"]);
+
+        assert!(buffer.reset_view());
+        assert_eq!(visible(&buffer), ["e", "f"]);
+        feed(&mut buffer, b"\r\ng");
+        assert_eq!(visible(&buffer), ["f", "g"]);
+    }
+
+    #[test]
+    fn erase_saved_lines_clears_scrollback() {
+        let mut buffer = Buffer::new(Coord { x: 10, y: 2 });
+        feed(&mut buffer, b"1\r\n2\r\n3\r\n4");
+        buffer.scroll_view(1);
+        feed(&mut buffer, b"\x1b[3J");
+        assert!(buffer.scrollback().is_empty());
+        assert_eq!(buffer.first_line(), 2);
+        assert_eq!(buffer.view_offset(), 0);
+        assert_eq!(visible(&buffer), ["3", "4"]);
+    }
+
+    fn screen(buffer: &Buffer) -> Vec<String> {
+        buffer.rows().iter().map(|r| r.text()).collect()
+    }
+
+    #[test]
+    fn alternate_screen_modes() {
+        let mut buffer = Buffer::new(Coord { x: 10, y: 2 });
+        feed(&mut buffer, b"main\x1b[?47h");
+        assert!(buffer.alternate_screen());
+        assert_eq!(screen(&buffer), ["", ""]);
+        feed(&mut buffer, b"\ralt\x1b[?47l");
+        assert!(!buffer.alternate_screen());
+        assert_eq!(screen(&buffer), ["main", ""]);
+
+        // 47 leaves the alternate screen as it was, 1047 clears it on the way out.
+        feed(&mut buffer, b"\x1b[?1047h");
+        assert_eq!(screen(&buffer), ["alt", ""]);
+        feed(&mut buffer, b"\x1b[?1047l\x1b[?47h");
+        assert_eq!(screen(&buffer), ["", ""]);
+        feed(&mut buffer, b"\x1b[?47l");
+        assert_eq!(screen(&buffer), ["main", ""]);
+    }
+
+    #[test]
+    fn mode_1049_saves_cursor_and_clears() {
+        let mut buffer = Buffer::new(Coord { x: 10, y: 3 });
+        feed(&mut buffer, b"\x1b[2;3Hmain\x1b[?1049h");
+        assert!(buffer.alternate_screen());
+        assert_eq!(screen(&buffer), ["", "", ""]);
+        feed(&mut buffer, b"\x1b[3;5Halt\r\n\r\n\x1b[?1049l");
+        assert!(!buffer.alternate_screen());
+        assert_eq!(screen(&buffer), ["", "  main", ""]);
+        assert_eq!(*buffer.cursor(), Coord { x: 6, y: 1 });
+        assert!(buffer.scrollback().is_empty());
+
+        feed(&mut buffer, b"\x1b[?1049h\x1b[?1049l");
+        assert_eq!(screen(&buffer), ["", "  main", ""]);
+
+        feed(&mut buffer, b"\x1b[1;2H\x1b[?1048h\x1b[3;3H\x1b[?1048l");
+        assert_eq!(*buffer.cursor(), Coord { x: 1, y: 0 });
+    }
+
+    #[test]
+    fn each_screen_saves_its_own_cursor() {
+        let mut buffer = Buffer::new(Coord { x: 10, y: 5 });
+        feed(&mut buffer, b"\x1b[2;3H\x1b7\x1b[?47h\x1b[4;5H\x1b7\x1b[?47l\x1b8");
+        assert_eq!(*buffer.cursor(), Coord { x: 2, y: 1 });
+        feed(&mut buffer, b"\x1b[?47h\x1b[H\x1b8");
+        assert_eq!(*buffer.cursor(), Coord { x: 4, y: 3 });
+        feed(&mut buffer, b"\x1b[?47l");
+
+        // Saving the cursor on the alternate screen doesn't lose the one 1049 saved.
+        feed(&mut buffer, b"\x1b[5;2H\x1b[?1049h\x1b[1;1H\x1b7\x1b[?1049l");
+        assert_eq!(*buffer.cursor(), Coord { x: 1, y: 4 });
+    }
+
+    #[test]
+    fn resize_pushes_rows_into_scrollback() {
+        let mut buffer = Buffer::new(Coord { x: 10, y: 4 });
+        feed(&mut buffer, b"1\r\n2\r\n3\r\n4");
+        buffer.resize(Coord { x: 10, y: 2 });
+        assert_eq!(lines(&buffer), ["1", "2", "3", "4"]);
+        assert_eq!(screen(&buffer), ["3", "4"]);
+        assert_eq!(*buffer.cursor(), Coord { x: 1, y: 1 });
+
+        // Rows below the cursor are dropped rather than pushing the cursor's line away.
+        feed(&mut buffer, b"\x1b[H");
+        buffer.resize(Coord { x: 10, y: 1 });
+        assert_eq!(lines(&buffer), ["1", "2", "3"]);
+
+        buffer.resize(Coord { x: 10, y: 3 });
+        assert_eq!(screen(&buffer), ["3", "", ""]);
+    }
+
+    #[test]
+    fn resize_on_alternate_screen_moves_both_saved_cursors() {
+        let mut buffer = Buffer::new(Coord { x: 10, y: 4 });
+        feed(&mut buffer, b"1\r\n2\r\n3\r\n4\x1b[?1049h\x1b[4;1H\x1b7");
+        buffer.resize(Coord { x: 10, y: 2 });
+        assert_eq!(buffer.scrollback().len(), 2);
+
+        feed(&mut buffer, b"\x1b[H\x1b8");
+        assert_eq!(*buffer.cursor(), Coord { x: 0, y: 1 });
+        feed(&mut buffer, b"\x1b[?1049l");
+        assert_eq!(screen(&buffer), ["3", "4"]);
+        assert_eq!(*buffer.cursor(), Coord { x: 1, y: 1 });
+        assert_eq!(lines(&buffer), ["1", "2", "3", "4"]);
+    }
+
+    /// A buffer with its rows numbered from 0, the cursor on the last one.
+    fn numbered(rows: usize) -> Buffer {
+        let mut buffer = Buffer::new(Coord { x: 10, y: rows });
+        let text: Vec<String> = (0..rows).map(|n| n.to_string()).collect();
+        feed(&mut buffer, text.join("\r\n").as_bytes());
+        buffer
+    }
+
+    #[test]
+    fn index_and_reverse_index_scroll_the_region() {
+        let mut buffer = numbered(5);
+        feed(&mut buffer, b"\x1b[2;4r");
+        assert_eq!(*buffer.cursor(), Coord { x: 0, y: 0 });
+
+        feed(&mut buffer, b"\x1b[4H\x1bD");
+        assert_eq!(screen(&buffer), ["0", "2", "3", "", "4"]);
+        assert!(buffer.scrollback().is_empty());
+        feed(&mut buffer, b"\x1b[2H\x1bM");
+        assert_eq!(screen(&buffer), ["0", "", "2", "3", "4"]);
+
+        // Below the region a linefeed on the last row goes nowhere.
+        feed(&mut buffer, b"\x1b[5H\n");
+        assert_eq!(screen(&buffer), ["0", "", "2", "3", "4"]);
+        assert_eq!(buffer.cursor().y, 4);
+
+        feed(&mut buffer, b"\x1b[r\x1b[5H\n");
+        assert_eq!(screen(&buffer), ["", "2", "3", "4", ""]);
+        assert_eq!(buffer.scrollback().len(), 1);
+    }
+
+    #[test]
+    fn scroll_up_and_down_in_region() {
+        let mut buffer = numbered(5);
+        feed(&mut buffer, b"\x1b[2;4r\x1b[S");
+        assert_eq!(screen(&buffer), ["0", "2", "3", "", "4"]);
+        feed(&mut buffer, b"\x1b[2T");
+        assert_eq!(screen(&buffer), ["0", "", "", "2", "4"]);
+        feed(&mut buffer, b"\x1b[9S");
+        assert_eq!(screen(&buffer), ["0", "", "", "", "4"]);
+        assert!(buffer.scrollback().is_empty());
+    }
+
+    #[test]
+    fn insert_and_delete_lines_in_region() {
+        let mut buffer = numbered(5);
+        feed(&mut buffer, b"\x1b[2;4r\x1b[3;2H\x1b[L");
+        assert_eq!(screen(&buffer), ["0", "1", "", "2", "4"]);
+        assert_eq!(*buffer.cursor(), Coord { x: 0, y: 2 });
+        feed(&mut buffer, b"\x1b[2M");
+        assert_eq!(screen(&buffer), ["0", "1", "", "", "4"]);
+
+        // Outside the region nothing moves.
+        feed(&mut buffer, b"\x1b[5H\x1b[L\x1b[1H\x1b[M");
+        assert_eq!(screen(&buffer), ["0", "1", "", "", "4"]);
+    }
+
+    #[test]
+    fn origin_mode_counts_from_top_margin() {
+        let mut buffer = numbered(5);
+        feed(&mut buffer, b"\x1b[2;4r\x1b[?6h");
+        assert_eq!(*buffer.cursor(), Coord { x: 0, y: 1 });
+        feed(&mut buffer, b"\x1b[2;3H");
+        assert_eq!(*buffer.cursor(), Coord { x: 2, y: 2 });
+        feed(&mut buffer, b"\x1b[9;1H");
+        assert_eq!(*buffer.cursor(), Coord { x: 0, y: 3 });
+        feed(&mut buffer, b"\x1b[9A");
+        assert_eq!(*buffer.cursor(), Coord { x: 0, y: 1 });
+        feed(&mut buffer, b"\x1b[?6l");
+        assert_eq!(*buffer.cursor(), Coord { x: 0, y: 0 });
+        feed(&mut buffer, b"\x1b[9;1H");
+        assert_eq!(*buffer.cursor(), Coord { x: 0, y: 4 });
+    }
+
+    #[test]
+    fn tab_stops() {
+        let mut buffer = Buffer::new(Coord { x: 30, y: 2 });
+        let mut column = |bytes: &[u8]| {
+            feed(&mut buffer, bytes);
+            buffer.cursor().x
+        };
+
+        assert_eq!(column(b"\t"), 8);
+        assert_eq!(column(b"\t\t"), 24);
+        assert_eq!(column(b"\t"), 29);
+        assert_eq!(column(b"\r\x1b[2I"), 16);
+        assert_eq!(column(b"\x1b[Z"), 8);
+        assert_eq!(column(b"\x1b[5Z"), 0);
+
+        // HTS sets a stop at the cursor, TBC clears the one there or all of them.
+        assert_eq!(column(b"\x1b[1;4H\x1bH\r\t"), 3);
+        assert_eq!(column(b"\x1b[1;9H\x1b[g\r\t\t"), 16);
+        assert_eq!(column(b"\x1b[Z"), 3);
+        assert_eq!(column(b"\x1b[3g\r\t"), 29);
+        assert_eq!(column(b"\x1b[Z"), 0);
+    }
+
+    fn printed(bytes: &[u8]) -> String {
+        let mut buffer = Buffer::new(Coord { x: 20, y: 1 });
+        feed(&mut buffer, bytes);
+        buffer.rows()[0].text()
+    }
+
+    #[test]
+    fn character_sets() {
+        // SCS designates the sets, B is ASCII and 0 the DEC line drawing set.
+        assert_eq!(printed(b"\x1b(0qx\x1b(Bq"), "\u{2500}\u{2502}q");
+        // SO and SI shift between G1 and G0.
+        assert_eq!(printed(b"\x1b)0q\x0eq\x0fq"), "q\u{2500}q");
+        // SS2 and SS3 take one character from G2 or G3.
+        assert_eq!(printed(b"\x1b*0\x1bNqq"), "\u{2500}q");
+        assert_eq!(printed(b"\x1b+0\x1bOjj"), "\u{2518}j");
+        // LS2 and LS3 shift to G2 and G3 until shifted back.
+        assert_eq!(printed(b"\x1b*0\x1bnlk\x0fl"), "\u{250c}\u{2510}l");
+        // DECSC saves the sets along with the cursor.
+        assert_eq!(printed(b"\x1b(0\x1b7\x1b(B\x1b8q"), "\u{2500}");
+    }
+
+    #[test]
+    fn dec_line_drawing_mapping() {
+        let graphics = StandardCharset::SpecialCharacterAndLineDrawing;
+        let mapped: String = "jklmnqtuvwx".chars().map(|c| graphics.map(c)).collect();
+        assert_eq!(mapped, "┘┐┌└┼─├┤┴┬│");
+        assert_eq!(graphics.map('`'), '◆');
+        assert_eq!(graphics.map('a'), '▒');
+        assert_eq!(graphics.map('A'), 'A');
+        assert_eq!(StandardCharset::Ascii.map('q'), 'q');
+    }
+
+    #[test]
+    fn wide_characters_take_two_cells() {
+        let mut buffer = Buffer::new(Coord { x: 10, y: 2 });
+        feed(&mut buffer, "a中b".as_bytes());
+        let cells = buffer.rows()[0].cells();
+        assert!(cells[1].is_wide() && cells[2].is_spacer());
+        assert_eq!(cells[3].text(), "b");
+        assert_eq!(buffer.cursor().x, 4);
+        assert_eq!(buffer.rows()[0].text(), "a中b");
+
+        // Writing over either half of a wide character blanks the whole of it.
+        feed(&mut buffer, b"\x1b[1;3Hx");
+        assert_eq!(buffer.rows()[0].text(), "a xb");
+        assert!(!buffer.rows()[0].cells()[1].is_wide());
+    }
+
+    #[test]
+    fn combining_and_joined_characters_share_a_cell() {
+        let mut buffer = Buffer::new(Coord { x: 10, y: 3 });
+        feed(&mut buffer, "e\u{301}x".as_bytes());
+        assert_eq!(buffer.rows()[0].cells()[0].text(), "e\u{301}");
+        assert_eq!(buffer.cursor().x, 2);
+
+        // The mark goes on the wide character, not the spacer after it.
+        feed(&mut buffer, "\r\n中\u{301}".as_bytes());
+        assert_eq!(buffer.rows()[1].cells()[0].text(), "中\u{301}");
+        assert!(buffer.rows()[1].cells()[1].is_spacer());
+
+        feed(&mut buffer, "\r\n\u{1f469}\u{200d}\u{1f4bb}!".as_bytes());
+        let row = &buffer.rows()[2];
+        assert_eq!(row.cells()[0].text(), "\u{1f469}\u{200d}\u{1f4bb}");
+        assert_eq!(row.cells()[2].text(), "!");
+    }
+
+    #[test]
+    fn marks_attach_in_the_last_column() {
+        let mut buffer = Buffer::new(Coord { x: 3, y: 2 });
+        feed(&mut buffer, "abc\u{301}".as_bytes());
+        assert_eq!(buffer.rows()[0].cells()[2].text(), "c\u{301}");
+        assert_eq!(*buffer.cursor(), Coord { x: 2, y: 0 });
     }
 
-    fn raw() -> String {
-        String::new()
+    #[test]
+    fn wide_character_in_last_column_wraps_whole() {
+        let mut buffer = Buffer::new(Coord { x: 5, y: 2 });
+        feed(&mut buffer, "abcd中".as_bytes());
+        assert_eq!(buffer.rows()[0].text(), "abcd");
+        assert!(buffer.rows()[0].wrapped());
+        assert!(buffer.rows()[1].cells()[0].is_wide());
+        assert_eq!(*buffer.cursor(), Coord { x: 2, y: 1 });
     }
 }
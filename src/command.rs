@@ -0,0 +1,240 @@
+use crate::event::Context;
+use crate::keys::{Key, KeyCode};
+use crate::layout::{Direction, Orientation};
+use crate::pty::PseudoConsole;
+
+use std::collections::HashMap;
+use std::io::Result;
+use std::path::PathBuf;
+
+/// Something the multiplexer itself does, rather than a console.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Splits the active pane into two panes side by side
+    SplitHorizontal,
+    /// Splits the active pane into two panes on top of each other
+    SplitVertical,
+    SelectPane(Direction),
+    NextPane,
+    RotatePanes,
+    /// Exchanges the active pane with the next one, the active pane moving with it
+    SwapPane,
+    /// Gives every pane in a row or column of panes the same size
+    Rebalance,
+    KillPane,
+    ToggleZoom,
+    /// Scrolls the view of the active pane a page back into its history
+    ScrollPageUp,
+    /// Scrolls the view of the active pane a page towards the live screen
+    ScrollPageDown,
+    /// Puts the active pane in copy mode to select text from its history
+    CopyMode,
+    /// Enters copy mode and asks for text to search the history of the active pane for
+    SearchForward,
+    SearchBackward,
+    /// Pastes the named paste buffer into the active pane, or the most recent one
+    PasteBuffer(Option<String>),
+    /// Lists the paste buffers over the active pane to pick one to paste
+    ChooseBuffer,
+    /// Deletes the named paste buffer, or the most recent one
+    DeleteBuffer(Option<String>),
+    /// Writes a paste buffer to a file
+    SaveBuffer(PathBuf, Option<String>),
+    /// Reads a file into a paste buffer
+    LoadBuffer(PathBuf, Option<String>),
+    NewTab,
+    NextTab,
+    PreviousTab,
+    SelectTab(usize),
+    CloseTab,
+    /// Names the active tab, or asks for a name in the status line
+    RenameTab(Option<String>),
+    /// Kills every pane and leaves conmux, giving the host terminal back
+    KillServer,
+    /// Sends the prefix key itself to the active console
+    SendPrefix,
+}
+
+impl Command {
+    /// Parses a command by name, like `split-horizontal`, `select-tab 2` or `save-buffer clip.txt`.
+    pub fn parse(name: &str) -> Option<Command> {
+        let words: Vec<&str> = name.split_whitespace().collect();
+        let command = match words.as_slice() {
+            ["split-horizontal"] => Command::SplitHorizontal,
+            ["split-vertical"] => Command::SplitVertical,
+            ["select-pane-up"] => Command::SelectPane(Direction::Up),
+            ["select-pane-down"] => Command::SelectPane(Direction::Down),
+            ["select-pane-left"] => Command::SelectPane(Direction::Left),
+            ["select-pane-right"] => Command::SelectPane(Direction::Right),
+            ["next-pane"] => Command::NextPane,
+            ["rotate-panes"] => Command::RotatePanes,
+            ["swap-pane"] => Command::SwapPane,
+            ["rebalance"] => Command::Rebalance,
+            ["kill-pane"] => Command::KillPane,
+            ["toggle-zoom"] => Command::ToggleZoom,
+            ["scroll-page-up"] => Command::ScrollPageUp,
+            ["scroll-page-down"] => Command::ScrollPageDown,
+            ["copy-mode"] => Command::CopyMode,
+            ["search-forward"] => Command::SearchForward,
+            ["search-backward"] => Command::SearchBackward,
+            ["paste-buffer"] => Command::PasteBuffer(None),
+            ["paste-buffer", name] => Command::PasteBuffer(Some(name.to_string())),
+            ["choose-buffer"] => Command::ChooseBuffer,
+            ["delete-buffer"] => Command::DeleteBuffer(None),
+            ["delete-buffer", name] => Command::DeleteBuffer(Some(name.to_string())),
+            ["save-buffer", path] => Command::SaveBuffer(path.into(), None),
+            ["save-buffer", path, name] => Command::SaveBuffer(path.into(), Some(name.to_string())),
+            ["load-buffer", path] => Command::LoadBuffer(path.into(), None),
+            ["load-buffer", path, name] => Command::LoadBuffer(path.into(), Some(name.to_string())),
+            ["new-tab"] => Command::NewTab,
+            ["next-tab"] => Command::NextTab,
+            ["previous-tab"] => Command::PreviousTab,
+            ["select-tab", n] => Command::SelectTab(n.parse().ok()?),
+            ["close-tab"] => Command::CloseTab,
+            ["rename-tab"] => Command::RenameTab(None),
+            ["rename-tab", name @ ..] => Command::RenameTab(Some(name.join(" "))),
+            ["kill-server"] => Command::KillServer,
+            ["send-prefix"] => Command::SendPrefix,
+            _ => return None,
+        };
+        Some(command)
+    }
+}
+
+/// Keys that run commands when pressed after the prefix key.
+pub struct Bindings {
+    prefix: Key,
+    keys: HashMap<Key, Command>,
+}
+
+impl Bindings {
+    pub fn prefix(&self) -> Key {
+        self.prefix
+    }
+
+    pub fn set_prefix(&mut self, prefix: Key) {
+        self.prefix = prefix;
+    }
+
+    pub fn bind(&mut self, key: Key, command: Command) {
+        self.keys.insert(key, command);
+    }
+
+    pub fn unbind(&mut self, key: &Key) {
+        self.keys.remove(key);
+    }
+
+    /// The command a key runs after the prefix, pressing the prefix again sends it through.
+    pub fn get(&self, key: &Key) -> Option<Command> {
+        match self.keys.get(key) {
+            Some(command) => Some(command.clone()),
+            None if *key == self.prefix => Some(Command::SendPrefix),
+            None => None,
+        }
+    }
+}
+
+impl Default for Bindings {
+    /// The tmux bindings for the commands conmux has.
+    fn default() -> Bindings {
+        let mut bindings = Bindings {
+            prefix: Key::ctrl('b'),
+            keys: HashMap::new(),
+        };
+
+        let char = |c| Key::new(KeyCode::Char(c));
+        bindings.bind(char('%'), Command::SplitHorizontal);
+        bindings.bind(char('"'), Command::SplitVertical);
+        bindings.bind(Key::new(KeyCode::Up), Command::SelectPane(Direction::Up));
+        bindings.bind(Key::new(KeyCode::Down), Command::SelectPane(Direction::Down));
+        bindings.bind(Key::new(KeyCode::Left), Command::SelectPane(Direction::Left));
+        bindings.bind(Key::new(KeyCode::Right), Command::SelectPane(Direction::Right));
+        bindings.bind(char('o'), Command::NextPane);
+        bindings.bind(Key::ctrl('o'), Command::RotatePanes);
+        bindings.bind(char('}'), Command::SwapPane);
+        bindings.bind(char('E'), Command::Rebalance);
+        bindings.bind(char('x'), Command::KillPane);
+        bindings.bind(char('z'), Command::ToggleZoom);
+        bindings.bind(Key::new(KeyCode::PageUp), Command::ScrollPageUp);
+        bindings.bind(Key::new(KeyCode::PageDown), Command::ScrollPageDown);
+        bindings.bind(char('['), Command::CopyMode);
+        bindings.bind(char(']'), Command::PasteBuffer(None));
+        bindings.bind(char('='), Command::ChooseBuffer);
+        bindings.bind(char('-'), Command::DeleteBuffer(None));
+        bindings.bind(char('c'), Command::NewTab);
+        bindings.bind(char('n'), Command::NextTab);
+        bindings.bind(char('p'), Command::PreviousTab);
+        bindings.bind(char('&'), Command::CloseTab);
+        bindings.bind(char(','), Command::RenameTab(None));
+        bindings.bind(char('\\'), Command::KillServer);
+        for n in 0..10 {
+            bindings.bind(char((b'0' + n as u8) as char), Command::SelectTab(n));
+        }
+        bindings
+    }
+}
+
+/// Runs a command against the panes and tabs of a context.
+pub fn execute<T>(ctx: &mut Context<T>, command: Command) -> Result<()>
+where
+    T: PseudoConsole<T>,
+{
+    match command {
+        Command::SplitHorizontal => {
+            ctx.split(Orientation::Horizontal)?;
+        }
+        Command::SplitVertical => {
+            ctx.split(Orientation::Vertical)?;
+        }
+        Command::SelectPane(direction) => ctx.select_pane(direction)?,
+        Command::NextPane => ctx.next_pane()?,
+        Command::RotatePanes => ctx.rotate_panes(),
+        Command::SwapPane => {
+            let leaves = ctx.layout().map(|l| l.leaves()).unwrap_or_default();
+            let active = ctx.active_console_index();
+            if let Some(position) = leaves.iter().position(|idx| *idx == active) {
+                ctx.swap_panes(active, leaves[(position + 1) % leaves.len()]);
+            }
+        }
+        Command::Rebalance => ctx.rebalance_panes(),
+        Command::KillPane => {
+            let active = ctx.active_console_index();
+            ctx.delete_console(active);
+        }
+        Command::ToggleZoom => ctx.toggle_zoom(),
+        Command::ScrollPageUp => ctx.scroll_active_view(ctx.pane_area().size.y as isize),
+        Command::ScrollPageDown => ctx.scroll_active_view(-(ctx.pane_area().size.y as isize)),
+        Command::CopyMode => ctx.enter_copy_mode(),
+        Command::SearchForward => ctx.start_search(true),
+        Command::SearchBackward => ctx.start_search(false),
+        Command::PasteBuffer(name) => ctx.paste_buffer(name.as_deref())?,
+        Command::ChooseBuffer => ctx.choose_buffer(),
+        Command::DeleteBuffer(name) => {
+            let name = ctx.buffers().find(name.as_deref())?.name().to_owned();
+            ctx.buffers_mut().delete(&name);
+        }
+        Command::SaveBuffer(path, name) => ctx.buffers().save(name.as_deref(), &path)?,
+        Command::LoadBuffer(path, name) => {
+            ctx.buffers_mut().load(&path, name.as_deref())?;
+        }
+        Command::NewTab => {
+            ctx.new_tab()?;
+        }
+        Command::NextTab => ctx.next_tab(),
+        Command::PreviousTab => ctx.previous_tab(),
+        Command::SelectTab(tab) => ctx.select_tab(tab)?,
+        Command::CloseTab => {
+            let active = ctx.active_tab_index();
+            ctx.close_tab(active);
+        }
+        Command::RenameTab(Some(name)) => {
+            let active = ctx.active_tab_index();
+            ctx.rename_tab(active, name);
+        }
+        Command::RenameTab(None) => ctx.start_rename_tab(),
+        Command::KillServer => ctx.kill_server(),
+        // The input dispatcher writes the prefix itself, it knows what it is bound to.
+        Command::SendPrefix => (),
+    }
+    Ok(())
+}
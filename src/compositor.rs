@@ -0,0 +1,556 @@
+use crate::ansitypes::*;
+use crate::buffer::{Buffer, Cell, Style};
+use crate::copy::{CopyMode, Point};
+use crate::event::{Action, EventContext};
+use crate::pty::PseudoConsole;
+use crate::surface::{Coord, Rect};
+
+use std::fmt::Write as FmtWrite;
+use std::io::{stdout, Write};
+use std::thread;
+use std::time::Duration;
+use unicode_width::UnicodeWidthChar;
+
+const CSI: &str = "\x1b[";
+
+/// How often the host screen is brought up to date, if anything changed.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(16);
+
+/// A pane to be drawn onto the host surface.
+pub struct Pane<'a> {
+    pub buffer: &'a Buffer,
+    pub rect: Rect,
+    pub active: bool,
+    pub copy: Option<&'a CopyMode>,
+    /// Drawn in place of the buffer, if the pane has one
+    pub menu: Option<Menu>,
+}
+
+/// A list drawn over a pane in place of its contents, like the buffer chooser.
+pub struct Menu {
+    pub items: Vec<String>,
+    pub selected: usize,
+}
+
+/// The contents of the whole host surface.
+///
+/// Cells that are not covered by any pane are left as `None` and become borders.
+#[derive(Clone, PartialEq)]
+pub struct Frame {
+    cells: Vec<Option<Cell>>,
+    dimensions: Coord,
+}
+
+impl Frame {
+    fn new(dimensions: Coord) -> Frame {
+        Frame {
+            cells: vec![None; dimensions.x * dimensions.y],
+            dimensions,
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<&Cell> {
+        if x >= self.dimensions.x || y >= self.dimensions.y {
+            return None;
+        }
+        self.cells[y * self.dimensions.x + x].as_ref()
+    }
+
+    fn set(&mut self, x: usize, y: usize, cell: Cell) {
+        if x < self.dimensions.x && y < self.dimensions.y {
+            self.cells[y * self.dimensions.x + x] = Some(cell);
+        }
+    }
+
+    /// Copies the visible region of a pane's buffer into its rectangle, cropping what doesn't fit.
+    ///
+    /// The selection of a pane in copy mode is drawn in reverse video, and the matches of its
+    /// last search in color.
+    fn draw_pane(&mut self, pane: &Pane) {
+        let rows = pane.buffer.visible_rows();
+        let top = pane.buffer.first_line() + pane.buffer.view_start();
+        let search = pane.copy.and_then(|copy| copy.search());
+        let current = search.and_then(|s| s.current());
+        let matches = search.map_or(&[][..], |s| {
+            s.matches_between(top, top + pane.rect.size.y.saturating_sub(1))
+        });
+
+        for y in 0..pane.rect.size.y {
+            for x in 0..pane.rect.size.x {
+                let mut cell: Cell = rows
+                    .get(y)
+                    .and_then(|row| row.cells().get(x))
+                    .cloned()
+                    .unwrap_or_default();
+                // Half a wide character can't be drawn where the pane cuts it off.
+                if cell.is_wide() && x + 1 == pane.rect.size.x {
+                    cell = Cell::blank(cell.style);
+                }
+                let point = Point {
+                    line: top + y,
+                    column: x,
+                };
+                if let Some(m) = matches.iter().find(|m| m.contains(point)) {
+                    cell.style.fg = Color::Named(NamedColor::Black);
+                    cell.style.bg = if Some(*m) == current {
+                        Color::Named(NamedColor::Magenta)
+                    } else {
+                        Color::Named(NamedColor::Cyan)
+                    };
+                }
+                if pane.copy.map_or(false, |copy| copy.selected(point)) {
+                    cell.style.reverse = !cell.style.reverse;
+                }
+                self.set(pane.rect.origin.x + x, pane.rect.origin.y + y, cell);
+            }
+        }
+
+        if let Some(menu) = pane.menu.as_ref() {
+            self.draw_menu(menu, pane.rect);
+        }
+    }
+
+    /// Fills a rectangle with the items of a menu, scrolled so the selected item shows.
+    fn draw_menu(&mut self, menu: &Menu, rect: Rect) {
+        let first = (menu.selected + 1).saturating_sub(rect.size.y);
+        for y in 0..rect.size.y {
+            let style = Style {
+                reverse: first + y == menu.selected,
+                ..Style::default()
+            };
+
+            // Padded with spaces rather than blanks, so the selected item is reversed all the way.
+            let text = menu.items.get(first + y).map_or("", |i| i.as_str());
+            let origin = Coord {
+                x: rect.origin.x,
+                y: rect.origin.y + y,
+            };
+            self.draw_text(origin, rect.size.x, text, style, Cell::new(' ', style));
+        }
+    }
+
+    /// Writes a line of text cut to `width` columns, padding the rest with `pad`.
+    fn draw_text(&mut self, origin: Coord, width: usize, text: &str, style: Style, pad: Cell) {
+        let mut x = 0;
+        for c in text.chars() {
+            let cell = Cell::new(c, style);
+            match c.width() {
+                Some(1) if x < width => self.set(origin.x + x, origin.y, cell),
+                Some(2) if x + 1 < width => {
+                    self.set(origin.x + x, origin.y, cell);
+                    self.set(origin.x + x + 1, origin.y, Cell::spacer(style));
+                }
+                Some(0) => continue,
+                _ => break,
+            }
+            x += c.width().unwrap_or(0);
+        }
+        for x in x..width {
+            self.set(origin.x + x, origin.y, pad.clone());
+        }
+    }
+
+    /// Fills the last row with the status line.
+    fn draw_status(&mut self, status: &str) {
+        if self.dimensions.y == 0 {
+            return;
+        }
+
+        let style = Style {
+            fg: Color::Named(NamedColor::Black),
+            bg: Color::Named(NamedColor::Green),
+            ..Style::default()
+        };
+
+        let origin = Coord {
+            x: 0,
+            y: self.dimensions.y - 1,
+        };
+        self.draw_text(origin, self.dimensions.x, status, style, Cell::blank(style));
+    }
+
+    /// Fills every uncovered cell with a line drawing glyph joining its neighbours.
+    fn draw_borders(&mut self, active: Option<Rect>) {
+        let style = Style {
+            fg: Color::Named(NamedColor::White),
+            ..Style::default()
+        };
+        let active_style = Style {
+            fg: Color::Named(NamedColor::Green),
+            ..Style::default()
+        };
+
+        // Borders join up with the cells around them that aren't covered by a pane either,
+        // which has to be decided before any of them are filled in.
+        let width = self.dimensions.x;
+        let uncovered: Vec<bool> = self.cells.iter().map(|c| c.is_none()).collect();
+        let border = |x: usize, y: usize| uncovered[y * width + x];
+
+        for y in 0..self.dimensions.y {
+            for x in 0..self.dimensions.x {
+                if !border(x, y) {
+                    continue;
+                }
+
+                let up = y > 0 && border(x, y - 1);
+                let down = y + 1 < self.dimensions.y && border(x, y + 1);
+                let left = x > 0 && border(x - 1, y);
+                let right = x + 1 < self.dimensions.x && border(x + 1, y);
+
+                let glyph = match (up, down, left, right) {
+                    (true, true, true, true) => '┼',
+                    (true, true, false, true) => '├',
+                    (true, true, true, false) => '┤',
+                    (false, true, true, true) => '┬',
+                    (true, false, true, true) => '┴',
+                    (false, true, false, true) => '┌',
+                    (false, true, true, false) => '┐',
+                    (true, false, false, true) => '└',
+                    (true, false, true, false) => '┘',
+                    (false, false, true, _) | (false, false, _, true) => '─',
+                    _ => '│',
+                };
+
+                let adjacent = active.map_or(false, |rect| {
+                    x + 1 >= rect.origin.x
+                        && y + 1 >= rect.origin.y
+                        && x <= rect.origin.x + rect.size.x
+                        && y <= rect.origin.y + rect.size.y
+                });
+
+                let cell = Cell::new(glyph, if adjacent { active_style } else { style });
+                self.cells[y * self.dimensions.x + x] = Some(cell);
+            }
+        }
+    }
+}
+
+/// Renders pane buffers onto the host, sending only what changed since the last frame.
+pub struct Compositor {
+    previous: Option<Frame>,
+}
+
+impl Compositor {
+    pub fn new() -> Compositor {
+        Compositor { previous: None }
+    }
+
+    /// Forgets what is on the host screen, so the next render redraws everything.
+    pub fn invalidate(&mut self) {
+        self.previous = None;
+    }
+
+    pub fn compose(&self, dimensions: Coord, panes: &[Pane], status: &str) -> Frame {
+        let mut frame = Frame::new(dimensions);
+        for pane in panes {
+            frame.draw_pane(pane);
+        }
+        frame.draw_status(status);
+        frame.draw_borders(panes.iter().find(|p| p.active).map(|p| p.rect));
+        frame
+    }
+
+    /// Returns the ANSI sequences that bring the host screen up to date with the panes.
+    pub fn render(&mut self, dimensions: Coord, panes: &[Pane], status: &str) -> Vec<u8> {
+        let frame = self.compose(dimensions, panes, status);
+        let mut out = String::new();
+
+        // Hide the cursor while drawing so it doesn't flicker across the screen.
+        out.push_str("\x1b[?25l");
+
+        let previous = match self.previous.take() {
+            Some(previous) if previous.dimensions == dimensions => Some(previous),
+            _ => {
+                out.push_str(CSI);
+                out.push_str("0m");
+                out.push_str(CSI);
+                out.push_str("2J");
+                None
+            }
+        };
+
+        let mut pen: Option<Style> = None;
+        let mut position: Option<Coord> = None;
+
+        for y in 0..dimensions.y {
+            for x in 0..dimensions.x {
+                let cell = frame.get(x, y).cloned().unwrap_or_default();
+                let unchanged = previous
+                    .as_ref()
+                    .and_then(|p| p.get(x, y))
+                    .map_or(false, |p| *p == cell);
+
+                // The host draws the right half of a wide character along with the left.
+                if unchanged || cell.is_spacer() {
+                    continue;
+                }
+
+                if position != Some(Coord { x, y }) {
+                    write!(out, "{}{};{}H", CSI, y + 1, x + 1).unwrap();
+                }
+
+                if pen != Some(cell.style) {
+                    out.push_str(&sgr(&cell.style));
+                    pen = Some(cell.style);
+                }
+
+                out.push_str(&cell.text());
+                let width = if cell.is_wide() { 2 } else { 1 };
+                position = Some(Coord { x: x + width, y });
+            }
+        }
+
+        out.push_str(CSI);
+        out.push_str("0m");
+
+        // The cursor stays hidden while the active pane is showing its scrollback, unless
+        // it is in copy mode and the cursor is the one moving over the history.
+        let copy_cursor = panes
+            .iter()
+            .find(|p| p.active)
+            .and_then(|p| Some((p, p.copy?.cursor())));
+        if let Some((pane, cursor)) = copy_cursor {
+            let top = pane.buffer.first_line() + pane.buffer.view_start();
+            if cursor.line >= top
+                && cursor.line - top < pane.rect.size.y
+                && cursor.column < pane.rect.size.x
+            {
+                write!(
+                    out,
+                    "{}{};{}H\x1b[?25h",
+                    CSI,
+                    pane.rect.origin.y + cursor.line - top + 1,
+                    pane.rect.origin.x + cursor.column + 1
+                )
+                .unwrap();
+            }
+        } else if let Some(pane) = panes.iter().find(|p| p.active && p.buffer.view_offset() == 0) {
+            let cursor = pane.buffer.cursor();
+            if cursor.x < pane.rect.size.x && cursor.y < pane.rect.size.y {
+                write!(
+                    out,
+                    "{}{};{}H",
+                    CSI,
+                    pane.rect.origin.y + cursor.y + 1,
+                    pane.rect.origin.x + cursor.x + 1
+                )
+                .unwrap();
+                if pane.buffer.mode(Mode::ShowCursor) {
+                    out.push_str("\x1b[?25h");
+                }
+            }
+        }
+
+        self.previous = Some(frame);
+        out.into_bytes()
+    }
+}
+
+/// Select Graphic Rendition sequence that sets the pen to exactly `style`.
+pub fn sgr(style: &Style) -> String {
+    let mut params = vec![String::from("0")];
+
+    if style.bold {
+        params.push(String::from("1"));
+    }
+    if style.dim {
+        params.push(String::from("2"));
+    }
+    if style.italic {
+        params.push(String::from("3"));
+    }
+    if style.underline {
+        params.push(String::from("4"));
+    }
+    if style.blink {
+        params.push(String::from("5"));
+    }
+    if style.reverse {
+        params.push(String::from("7"));
+    }
+    if style.hidden {
+        params.push(String::from("8"));
+    }
+    if style.strike {
+        params.push(String::from("9"));
+    }
+
+    if let Some(fg) = color_param(style.fg, true) {
+        params.push(fg);
+    }
+    if let Some(bg) = color_param(style.bg, false) {
+        params.push(bg);
+    }
+
+    format!("{}{}m", CSI, params.join(";"))
+}
+
+fn color_param(color: Color, foreground: bool) -> Option<String> {
+    let (base, bright, extended) = if foreground {
+        (30, 90, 38)
+    } else {
+        (40, 100, 48)
+    };
+
+    match color {
+        Color::Named(named) => {
+            // Dim colors are drawn as their normal counterpart, the dim attribute carries the rest.
+            let named = match named {
+                NamedColor::DimForeground => NamedColor::Foreground,
+                n if n >= NamedColor::DimBlack && n <= NamedColor::DimWhite => n.to_bright(),
+                n => n,
+            };
+            match named as usize {
+                n @ 0..=7 => Some(format!("{}", base + n)),
+                n @ 8..=15 => Some(format!("{}", bright + n - 8)),
+                _ => None,
+            }
+        }
+        Color::Indexed(idx) => Some(format!("{};5;{}", extended, idx)),
+        Color::Spec(r, g, b) => Some(format!("{};2;{};{};{}", extended, r, g, b)),
+    }
+}
+
+/// Draws every pane onto the host screen whenever their buffers change.
+pub fn register_render_handler<T>(ectx: &mut EventContext<T>)
+where
+    T: PseudoConsole<T>,
+{
+    ectx.sender(|tx| loop {
+        thread::sleep(REDRAW_INTERVAL);
+        if tx.send(Action::Redraw).is_err() {
+            return Ok(());
+        }
+    });
+
+    let mut compositor = Compositor::new();
+    ectx.handler(move |ctx, action| {
+        match action {
+            Action::HostResize(_) => compositor.invalidate(),
+            Action::Redraw => {
+                if !ctx.take_dirty() {
+                    return None;
+                }
+
+                let frame = compositor.render(*ctx.dimensions(), &ctx.panes(), &ctx.status());
+                let stdout = stdout();
+                let mut lock = stdout.lock();
+                lock.write_all(&frame).ok();
+                lock.flush().ok();
+            }
+            _ => (),
+        }
+        None
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::parse;
+
+    fn buffer(width: usize, rows: usize, text: &[u8]) -> Buffer {
+        let mut buffer = Buffer::new(Coord { x: width, y: rows });
+        feed(&mut buffer, text);
+        buffer
+    }
+
+    fn feed(buffer: &mut Buffer, text: &[u8]) {
+        for action in parse(text) {
+            buffer.perform(&action);
+        }
+    }
+
+    fn pane(buffer: &Buffer, x: usize, y: usize, width: usize, height: usize) -> Pane<'_> {
+        Pane {
+            buffer,
+            rect: Rect::new(Coord { x, y }, Coord { x: width, y: height }),
+            active: false,
+            copy: None,
+            menu: None,
+        }
+    }
+
+    fn row(frame: &Frame, y: usize) -> String {
+        (0..frame.dimensions.x)
+            .filter_map(|x| frame.get(x, y))
+            .map(|c| if c.is_empty() { String::from(" ") } else { c.text() })
+            .collect()
+    }
+
+    const SIZE: Coord = Coord { x: 11, y: 3 };
+
+    #[test]
+    fn panes_side_by_side() {
+        let left = buffer(5, 2, b"left\r\nL");
+        let right = buffer(5, 2, b"right\r\nR");
+        let panes = [pane(&left, 0, 0, 5, 2), pane(&right, 6, 0, 5, 2)];
+        let frame = Compositor::new().compose(SIZE, &panes, "status");
+        assert_eq!(row(&frame, 0), "left │right");
+        assert_eq!(row(&frame, 1), "L    │R    ");
+        assert_eq!(row(&frame, 2), "status     ");
+    }
+
+    #[test]
+    fn second_render_sends_only_changes() {
+        let left = buffer(5, 2, b"left");
+        let mut right = buffer(5, 2, b"right");
+        let mut compositor = Compositor::new();
+        {
+            let mut panes = [pane(&left, 0, 0, 5, 2), pane(&right, 6, 0, 5, 2)];
+            panes[0].active = true;
+            let first = compositor.render(SIZE, &panes, "");
+            assert!(first.starts_with(b"\x1b[?25l\x1b[0m\x1b[2J"));
+            assert_eq!(compositor.render(SIZE, &panes, ""), b"\x1b[?25l\x1b[0m\x1b[1;5H\x1b[?25h");
+        }
+
+        feed(&mut right, b"\x1b[1;2HX");
+        let mut panes = [pane(&left, 0, 0, 5, 2), pane(&right, 6, 0, 5, 2)];
+        panes[0].active = true;
+        let expected = format!(
+            "\x1b[?25l\x1b[1;8H{}X\x1b[0m\x1b[1;5H\x1b[?25h",
+            sgr(&Style::default())
+        );
+        assert_eq!(String::from_utf8(compositor.render(SIZE, &panes, "")).unwrap(), expected);
+    }
+
+    #[test]
+    fn panes_are_cropped_to_their_rectangle() {
+        let wide = buffer(8, 2, b"abcdefgh\r\n12345678");
+        let other = buffer(5, 2, b"");
+        let panes = [pane(&wide, 0, 0, 5, 2), pane(&other, 6, 0, 5, 2)];
+        let frame = Compositor::new().compose(SIZE, &panes, "");
+        assert_eq!(row(&frame, 0), "abcde│     ");
+        assert_eq!(row(&frame, 1), "12345│     ");
+    }
+
+    #[test]
+    fn wide_character_cut_at_pane_edge() {
+        let text = buffer(10, 2, "abcd\u{4e2d}".as_bytes());
+        let panes = [pane(&text, 0, 0, 5, 2)];
+        let frame = Compositor::new().compose(SIZE, &panes, "");
+        let cell = frame.get(4, 0).unwrap();
+        assert!(cell.is_empty() && !cell.is_wide());
+    }
+
+    #[test]
+    fn borders_join_up() {
+        let size = Coord { x: 11, y: 5 };
+        let b = buffer(5, 4, b"");
+        let mut panes = [
+            pane(&b, 0, 0, 5, 4),
+            pane(&b, 6, 0, 5, 1),
+            pane(&b, 6, 2, 5, 2),
+        ];
+        panes[1].active = true;
+        let frame = Compositor::new().compose(size, &panes, "");
+        assert_eq!(row(&frame, 0), "     │     ");
+        assert_eq!(row(&frame, 1), "     ├─────");
+        assert_eq!(row(&frame, 3), "     │     ");
+
+        // Borders around the active pane are drawn in green.
+        let green = Color::Named(NamedColor::Green);
+        assert_eq!(frame.get(7, 1).unwrap().style.fg, green);
+        assert_ne!(frame.get(5, 3).unwrap().style.fg, green);
+    }
+}
@@ -0,0 +1,296 @@
+use crate::buffer::DEFAULT_SCROLLBACK_LIMIT;
+use crate::command::{Bindings, Command};
+use crate::copy::ModeKeys;
+use crate::keys::Key;
+
+use std::env;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+/// Name of the config file in the home directory.
+const CONFIG_FILE: &str = ".conmux.toml";
+
+/// Settings read from the conmux config file.
+///
+/// The file is a small subset of TOML, for example
+///
+/// ```toml
+/// prefix = "C-a"
+/// scrollback = 5000
+/// mode-keys = "vi"
+///
+/// [bindings]
+/// "|" = "split-horizontal"
+/// "-" = "split-vertical"
+/// "%" = "none"
+/// ```
+///
+/// Bindings are added to the defaults, binding a key to `none` removes it.
+pub struct Config {
+    pub bindings: Bindings,
+    /// Lines of history kept by each pane
+    pub scrollback: usize,
+    /// Keys used in copy mode
+    pub mode_keys: ModeKeys,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bindings: Bindings::default(),
+            scrollback: DEFAULT_SCROLLBACK_LIMIT,
+            mode_keys: ModeKeys::Emacs,
+        }
+    }
+}
+
+enum Section {
+    Root,
+    Bindings,
+}
+
+impl Config {
+    /// Where the config file is read from, `$CONMUX_CONFIG` or `.conmux.toml` in the home directory.
+    pub fn path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os("CONMUX_CONFIG") {
+            return Some(PathBuf::from(path));
+        }
+
+        env::var_os("HOME")
+            .or_else(|| env::var_os("USERPROFILE"))
+            .map(|home| PathBuf::from(home).join(CONFIG_FILE))
+    }
+
+    /// Reads the config file, falling back to the defaults if there isn't one.
+    pub fn load() -> Result<Config> {
+        let path = match Config::path() {
+            Some(path) => path,
+            None => return Ok(Config::default()),
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(source) => Config::parse(&source).map_err(|e| {
+                let errors: Vec<String> = e
+                    .to_string()
+                    .lines()
+                    .map(|line| format!("{}:{}", path.display(), line))
+                    .collect();
+                Error::new(ErrorKind::InvalidData, errors.join("\n"))
+            }),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Parses a config file, reporting every invalid line along with its line number.
+    pub fn parse(source: &str) -> Result<Config> {
+        let mut config = Config::default();
+        let mut section = Section::Root;
+        let mut errors = Vec::new();
+
+        for (number, line) in source.lines().enumerate() {
+            if let Err(message) = config.parse_line(line, &mut section) {
+                errors.push(format!("{}: {}", number + 1, message));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(Error::new(ErrorKind::InvalidData, errors.join("\n")))
+        }
+    }
+
+    fn parse_line(&mut self, line: &str, section: &mut Section) -> std::result::Result<(), String> {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        if line.starts_with('[') {
+            if !line.ends_with(']') {
+                return Err(String::from("unterminated section header"));
+            }
+            *section = match line[1..line.len() - 1].trim() {
+                "bindings" => Section::Bindings,
+                name => return Err(format!("unknown section `{}`", name)),
+            };
+            return Ok(());
+        }
+
+        let eq = match find_unquoted(line, '=') {
+            Some(eq) => eq,
+            None => return Err(String::from("expected `key = value`")),
+        };
+        let key = parse_key(line[..eq].trim())?;
+        let value = line[eq + 1..].trim();
+
+        match section {
+            Section::Root => match key.as_str() {
+                "prefix" => {
+                    let value = parse_string(value)?;
+                    let prefix = Key::parse(&value)
+                        .ok_or_else(|| format!("invalid prefix key `{}`", value))?;
+                    self.bindings.set_prefix(prefix);
+                }
+                "scrollback" => self.scrollback = parse_integer(value)?,
+                "mode-keys" => {
+                    let value = parse_string(value)?;
+                    self.mode_keys = ModeKeys::parse(&value)
+                        .ok_or_else(|| format!("mode-keys must be `vi` or `emacs`, found `{}`", value))?;
+                }
+                _ => return Err(format!("unknown setting `{}`", key)),
+            },
+            Section::Bindings => {
+                let value = parse_string(value)?;
+                let chord = Key::parse(&key).ok_or_else(|| format!("invalid key `{}`", key))?;
+                if value == "none" {
+                    self.bindings.unbind(&chord);
+                } else {
+                    let command = Command::parse(&value)
+                        .ok_or_else(|| format!("unknown command `{}`", value))?;
+                    self.bindings.bind(chord, command);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Cuts a `#` comment off the end of a line, unless it is inside a string.
+fn strip_comment(line: &str) -> &str {
+    match find_unquoted(line, '#') {
+        Some(idx) => &line[..idx],
+        None => line,
+    }
+}
+
+fn find_unquoted(line: &str, needle: char) -> Option<usize> {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == needle && !quoted => return Some(idx),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// A key is either bare, like `prefix`, or a string, like `"%"`.
+fn parse_key(key: &str) -> std::result::Result<String, String> {
+    if key.starts_with('"') {
+        parse_string(key)
+    } else if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Ok(key.to_owned())
+    } else {
+        Err(format!("invalid key `{}`, quote keys that aren't plain words", key))
+    }
+}
+
+fn parse_integer(value: &str) -> std::result::Result<usize, String> {
+    value
+        .replace('_', "")
+        .parse()
+        .map_err(|_| format!("expected a positive integer, found `{}`", value))
+}
+
+fn parse_string(value: &str) -> std::result::Result<String, String> {
+    if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return Err(format!("expected a quoted string, found `{}`", value));
+    }
+
+    let mut string = String::new();
+    let mut chars = value[1..value.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('\\') => string.push('\\'),
+                Some('"') => string.push('"'),
+                Some('n') => string.push('\n'),
+                Some('t') => string.push('\t'),
+                Some(c) => return Err(format!("unknown escape `\\{}`", c)),
+                None => return Err(String::from("unterminated string")),
+            },
+            '"' => return Err(String::from("unexpected `\"` in string")),
+            c => string.push(c),
+        }
+    }
+    Ok(string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::KeyCode;
+
+    /// The error messages of an invalid config, one per line.
+    fn errors(source: &str) -> Vec<String> {
+        match Config::parse(source) {
+            Ok(_) => panic!("config was accepted"),
+            Err(e) => e.to_string().lines().map(String::from).collect(),
+        }
+    }
+
+    #[test]
+    fn prefix_is_rebound() {
+        let config = Config::parse("prefix = \"C-a\"\n").unwrap();
+        assert_eq!(config.bindings.prefix(), Key::ctrl('a'));
+        // Pressing the new prefix twice sends it, the old one is an ordinary key.
+        assert_eq!(config.bindings.get(&Key::ctrl('a')), Some(Command::SendPrefix));
+        assert_eq!(config.bindings.get(&Key::ctrl('b')), None);
+    }
+
+    #[test]
+    fn chord_is_bound_to_command() {
+        let source = "# bindings\n[bindings]\n\"M-|\" = \"split-horizontal\" # side by side\n\"%\" = \"none\"\n";
+        let config = Config::parse(source).unwrap();
+
+        let mut chord = Key::new(KeyCode::Char('|'));
+        chord.modifiers.alt = true;
+        assert_eq!(config.bindings.get(&chord), Some(Command::SplitHorizontal));
+        assert_eq!(config.bindings.get(&Key::new(KeyCode::Char('%'))), None);
+        // Other defaults are kept.
+        assert_eq!(
+            config.bindings.get(&Key::new(KeyCode::Char('"'))),
+            Some(Command::SplitVertical)
+        );
+    }
+
+    #[test]
+    fn unknown_command_is_reported_with_its_line() {
+        let source = "scrollback = 10_000\n\n[bindings]\n\"x\" = \"explode\"\n";
+        assert_eq!(errors(source), vec!["4: unknown command `explode`"]);
+    }
+
+    #[test]
+    fn bad_key_name_is_reported_with_its_line() {
+        let source = "[bindings]\n\"C-Nope\" = \"new-tab\"\n\"X-a\" = \"new-tab\"\n";
+        assert_eq!(
+            errors(source),
+            vec!["2: invalid key `C-Nope`", "3: invalid key `X-a`"]
+        );
+    }
+
+    #[test]
+    fn malformed_lines_are_reported_with_their_lines() {
+        let source = "mode-keys = \"vi\"\nprefix\n[bindings\nscrollback = \"lots\"\n[colours]\n";
+        assert_eq!(
+            errors(source),
+            vec![
+                "2: expected `key = value`",
+                "3: unterminated section header",
+                "4: expected a positive integer, found `\"lots\"`",
+                "5: unknown section `colours`",
+            ]
+        );
+    }
+}
@@ -288,8 +288,8 @@ impl PseudoConsole<ConPty> for ConPty {
         &mut self.pipes.1
     }
 
-    fn reader(&self) -> &Self::Reader {
-        &self.pipes.0
+    fn reader(&self) -> Result<Self::Reader> {
+        Ok(self.pipes.0.clone())
     }
 
     fn keep_alive(&self) -> Self::KeepAlive {
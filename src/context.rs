@@ -1,83 +1,173 @@
 use crate::ansitypes::*;
-use crate::event::{Action, Column, EventContext, Line};
+use crate::command::{execute, Bindings, Command};
+use crate::event::{Action, EventContext, PASTE_END};
+use crate::keys::{Key, KeyCode, Modifiers};
+use crate::mouse::MouseEvent;
 use crate::pty::*;
 
-use crossbeam::channel::Sender;
-use ansi_escapes;
-use std::io::{stdin, Read, Write};
+use crossbeam::channel::{unbounded, RecvTimeoutError, Sender};
+use std::io::{stdin, Read, Result, Write};
+use std::thread;
+use std::time::Duration;
 use vte::Perform;
 
+/// How long an unfinished sequence waits for the rest of it before it is sent on as it is.
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(50);
+
 struct InputPerformer {
     tx: Sender<Action>,
+    /// Bytes of the sequence being parsed, forwarded as they are if conmux doesn't claim it
+    raw: Vec<u8>,
+    /// Whether the last sequence was an `SS3`, which makes the next character a key
+    ss3: bool,
+    /// Text pasted so far, while the host is in the middle of a bracketed paste
+    paste: Option<Vec<u8>>,
+}
+
+impl InputPerformer {
+    fn key(&mut self, key: Key) {
+        self.raw.clear();
+        self.tx.send(Action::HostKey(key)).unwrap();
+    }
+
+    /// Whether the start of a sequence is waiting for the rest of it.
+    fn pending(&self) -> bool {
+        self.paste.is_none() && !self.raw.is_empty()
+    }
+
+    /// Gives up on the rest of a sequence, a lone escape being the key by itself.
+    fn flush(&mut self) {
+        self.ss3 = false;
+        if self.raw == [C0::ESC] {
+            self.key(Key::new(KeyCode::Escape));
+        } else {
+            self.passthrough();
+        }
+    }
+
+    /// Sends the sequence just parsed through to the console untouched.
+    fn passthrough(&mut self) {
+        if !self.raw.is_empty() {
+            let raw = self.raw.split_off(0);
+            self.tx.send(Action::HostPassthrough(raw)).unwrap();
+        }
+    }
+
+    /// Sends through anything left over before the last `len` bytes, like an ignored sequence.
+    fn passthrough_before(&mut self, len: usize) {
+        if self.raw.len() > len {
+            let rest = self.raw.split_off(self.raw.len() - len);
+            self.passthrough();
+            self.raw = rest;
+        }
+    }
+
+    /// Sends through a finished string sequence, unless it still has to see the end of `ESC \`.
+    fn passthrough_string(&mut self) {
+        if self.raw.last() != Some(&C0::ESC) {
+            self.passthrough();
+        }
+    }
 }
 
 impl Perform for InputPerformer {
     fn print(&mut self, c: char) {
+        if self.ss3 {
+            self.ss3 = false;
+            self.passthrough_before(2 + c.len_utf8());
+            match Key::from_final(c, Modifiers::default()) {
+                Some(key) => self.key(key),
+                None => self.passthrough(),
+            }
+            return;
+        }
+
+        self.passthrough_before(c.len_utf8());
         if c == C0::DEL.into() {
-            self.tx.send(Action::HostBackspace).unwrap();
+            self.key(Key::new(KeyCode::Backspace));
         } else {
-            self.tx.send(Action::HostInput(c)).unwrap();
+            self.key(Key::new(KeyCode::Char(c)));
         }
     }
 
     fn execute(&mut self, byte: u8) {
-        let action = match byte {
-            C0::HT => Action::HostPutTabs(1),
-            C0::BS => Action::HostBackspace,
-            C0::CR => Action::HostCarriageReturn,
-            C0::LF | C0::VT | C0::FF => Action::HostLineFeed,
-            C0::SUB => Action::HostSubtitute,
-            C1::NEL => Action::HostNewline,
-            _ => { println!("{} [unhandled] execute", byte); Action::HostControlInput(byte) },
-        };
+        self.ss3 = false;
 
-        self.tx.send(action).unwrap();
+        // Control characters can turn up in the middle of a sequence, which carries on after them.
+        self.raw.pop();
+        match Key::from_control(byte) {
+            Some(key) => self.tx.send(Action::HostKey(key)).unwrap(),
+            None => self.tx.send(Action::HostPassthrough(vec![byte])).unwrap(),
+        }
     }
 
-    fn hook(&mut self, params: &[i64], intermediates: &[u8], ignore: bool) {}
+    fn hook(&mut self, _params: &[i64], _intermediates: &[u8], _ignore: bool) {}
 
-    fn put(&mut self, byte: u8) {}
+    fn put(&mut self, _byte: u8) {}
 
-    fn unhook(&mut self) {}
+    fn unhook(&mut self) {
+        self.passthrough_string();
+    }
 
-    fn osc_dispatch(&mut self, params: &[&[u8]]) {}
+    fn osc_dispatch(&mut self, _params: &[&[u8]]) {
+        self.passthrough_string();
+    }
 
     fn csi_dispatch(&mut self, params: &[i64], intermediates: &[u8], ignore: bool, c: char) {
-        macro_rules! arg_or_default {
-            (idx: $idx:expr, default: $default:expr) => {
-                params
-                    .get($idx)
-                    .and_then(|v| if *v == 0 { None } else { Some(*v) })
-                    .unwrap_or($default)
-            };
-        }
-
-        let action = match c {
-            'C' | 'a' => {
-                Action::HostCursorMoveForward(Column(arg_or_default!(idx: 0, default: 1) as usize))
-            }
-            'D' => {
-                Action::HostCursorMoveBackward(Column(arg_or_default!(idx: 0, default: 1) as usize))
-            }
-            'A' => {
-                Action::HostCursorMoveUp(Line(arg_or_default!(idx: 0, default: 1) as usize))
-            }
-            'B' => {
-                Action::HostCursorMoveUp(Line(arg_or_default!(idx: 0, default: 1) as usize))
-            }
-            '~' => {
-                match params[0] {
-                    3 => Action::HostDeleteKey,
-                    _ => Action::Noop
-                }
+        self.ss3 = false;
+        let modifiers = Modifiers::from_param(params.get(1).cloned().unwrap_or(1));
+
+        if !ignore && intermediates == b"<" && (c == 'M' || c == 'm') {
+            if let Some(event) = MouseEvent::from_sgr(params, c == 'm') {
+                self.raw.clear();
+                self.tx.send(Action::HostMouse(event)).unwrap();
+                return;
             }
-            _ => { println!("{}: [unhandled] csi", c); Action::Noop },
+        }
+
+        if !ignore && intermediates.is_empty() && c == '~' && params == [200] {
+            self.raw.clear();
+            self.paste = Some(Vec::new());
+            return;
+        }
+
+        if !ignore && intermediates.is_empty() && params.is_empty() && (c == 'I' || c == 'O') {
+            self.raw.clear();
+            self.tx.send(Action::HostFocus(c == 'I')).unwrap();
+            return;
+        }
+
+        let key = match c {
+            _ if ignore || !intermediates.is_empty() => None,
+            '~' => Key::from_tilde(params.first().cloned().unwrap_or(0), modifiers),
+            c => Key::from_final(c, modifiers),
         };
 
-        self.tx.send(action).unwrap();
+        match key {
+            Some(key) => self.key(key),
+            None => self.passthrough(),
+        }
     }
 
-    fn esc_dispatch(&mut self, params: &[i64], intermediates: &[u8], ignore: bool, byte: u8) {}
+    fn esc_dispatch(&mut self, _params: &[i64], intermediates: &[u8], _ignore: bool, byte: u8) {
+        self.ss3 = false;
+
+        // Either an escape sequence conmux doesn't know, or the string terminator of an
+        // OSC, DCS, SOS, PM or APC string, which is still waiting in the raw bytes.
+        if !intermediates.is_empty() || (byte == b'\\' && self.raw.len() > 2) {
+            return self.passthrough();
+        }
+
+        // An escape before a character is how terminals send it with Alt held.
+        match byte {
+            b'O' => self.ss3 = true,
+            byte => {
+                let mut key = Key::new(KeyCode::Char(byte as char));
+                key.modifiers.alt = true;
+                self.key(key);
+            }
+        }
+    }
 }
 
 /// Will lock stdin.
@@ -85,78 +175,458 @@ pub fn listen_input<T>(ectx: &mut EventContext<T>)
 where
     T: PseudoConsole<T>,
 {
-    ectx.sender(|tx| {
-        let stdin = stdin();
-        let mut lock = stdin.lock().bytes();
-        let mut sender = InputPerformer { tx: tx.clone() };
-        let mut parser = vte::Parser::new();
-        while let Some(Ok(c)) = lock.next() {
-            // tx.send(Action::KeyInputReceived(c)).unwrap();
-            parser.advance(&mut sender, c);
-        }
-        Ok(())
-    });
+    ectx.sender(|tx| perform_input(stdin(), tx));
 }
 
-pub fn register_console_handler<'a, T>(ectx: &mut EventContext<T>)
+/// Listens for host input from an arbitrary source instead of stdin.
+#[cfg(test)]
+pub fn listen_input_from<T, R>(ectx: &mut EventContext<T>, input: R)
 where
     T: PseudoConsole<T>,
+    R: Read + Send + 'static,
 {
-    ectx.handler(|ctx, action| {
-        let mut buf = vec![0; 4];
-        let writer = ctx.active_console_mut().writer();
-
-        match action {
-            Action::HostInput(c) => {
-                buf.clear();
-                buf.resize(c.len_utf8(), 0);
-                c.encode_utf8(&mut buf);
-                writer.write(&buf).unwrap();
-            }
+    ectx.sender(move |tx| perform_input(input, tx));
+}
 
-            Action::HostControlInput(c) => {
-                writer.write(&[c]).unwrap();
-            }
+/// Reads input in the chunks it arrives in, so the parser can wait on it with a timeout.
+fn read_input<R: Read>(mut input: R, chunks: Sender<Vec<u8>>) {
+    let mut buf = [0; 1024];
+    while let Ok(len) = input.read(&mut buf) {
+        if len == 0 || chunks.send(buf[..len].to_vec()).is_err() {
+            break;
+        }
+    }
+}
 
-            Action::HostCarriageReturn => {
-                writer.write(&[C0::CR]).unwrap();
-            }
+fn perform_input<R: Read + Send + 'static>(input: R, tx: Sender<Action>) -> Result<()> {
+    let (chunks_tx, chunks) = unbounded();
+    thread::spawn(move || read_input(input, chunks_tx));
 
-            Action::HostLineFeed => {
-                writer.write(&[C0::LF]).unwrap();
+    let mut sender = InputPerformer {
+        tx: tx.clone(),
+        raw: Vec::new(),
+        ss3: false,
+        paste: None,
+    };
+    let mut parser = vte::Parser::new();
+    loop {
+        let chunk = if sender.pending() {
+            match chunks.recv_timeout(ESCAPE_TIMEOUT) {
+                Err(RecvTimeoutError::Timeout) => {
+                    parser = vte::Parser::new();
+                    sender.flush();
+                    continue;
+                }
+                chunk => chunk.ok(),
             }
+        } else {
+            chunks.recv().ok()
+        };
+        let chunk = match chunk {
+            Some(chunk) => chunk,
+            None => break,
+        };
 
-            Action::HostBackspace => {
-                writer.write(&[C0::BS]).unwrap();
+        for c in chunk {
+            // Pasted text is taken as it is, up to the closing bracket, rather than as keys.
+            if let Some(paste) = sender.paste.as_mut() {
+                paste.push(c);
+                if paste.ends_with(PASTE_END) {
+                    let len = paste.len() - PASTE_END.len();
+                    paste.truncate(len);
+                    tx.send(Action::HostPaste(sender.paste.take().unwrap())).unwrap();
+                }
+                continue;
             }
 
-            Action::HostCursorMoveForward(cols) => {
-                let sequence = format!("{}", ansi_escapes::CursorForward(cols.0 as u16)).into_bytes();
-                writer.write(&sequence).unwrap();
-            }
+            sender.raw.push(c);
+            parser.advance(&mut sender, c);
+        }
+    }
+    Ok(())
+}
 
-            Action::HostCursorMoveBackward(cols) => {
-                let sequence = format!("{}", ansi_escapes::CursorBackward(cols.0 as u16)).into_bytes();
-                writer.write(&sequence).unwrap();
-            }
+/// Forwards host input to the active console, running commands for keys pressed after the prefix.
+pub fn register_console_handler<'a, T>(ectx: &mut EventContext<T>, bindings: Bindings)
+where
+    T: PseudoConsole<T>,
+{
+    let mut prefixed = false;
 
-            Action::HostCursorMoveUp(rows) => {
-                let sequence = format!("{}", ansi_escapes::CursorUp(rows.0 as u16)).into_bytes();
-                writer.write(&sequence).unwrap();
+    ectx.handler(move |ctx, action| {
+        let mut key = match action {
+            Action::HostKey(key) => key,
+            Action::HostPaste(text) => {
+                prefixed = false;
+                ctx.reset_active_view();
+                ctx.paste(&text).ok();
+                return None;
             }
-
-            Action::HostCursorMoveDown(rows) => {
-                let sequence = format!("{}", ansi_escapes::CursorDown(rows.0 as u16)).into_bytes();
-                writer.write(&sequence).unwrap();
+            Action::HostPassthrough(bytes) => {
+                // A sequence after the prefix isn't bound to anything, drop it like any other key.
+                if prefixed {
+                    prefixed = false;
+                    return None;
+                }
+                ctx.reset_active_view();
+                let writer = ctx.active_console_mut()?.writer();
+                writer.write_all(&bytes).ok();
+                writer.flush().ok();
+                return None;
             }
-            Action::HostDeleteKey => {
-                let sequence = String::from("\x1b[3~").into_bytes();
-                writer.write(&sequence).unwrap();
+            _ => return None,
+        };
+
+        if prefixed {
+            prefixed = false;
+            match bindings.get(&key) {
+                Some(Command::SendPrefix) => key = bindings.prefix(),
+                Some(command) => {
+                    execute(ctx, command).ok();
+                    return None;
+                }
+                None => return None,
             }
-            _ => (),
+        } else if key == bindings.prefix() {
+            prefixed = true;
+            return None;
+        }
+
+        if ctx.rename_prompt_key(key) || ctx.chooser_key(key) || ctx.copy_mode_key(key) {
+            return None;
         }
 
-        writer.flush().unwrap();
+        ctx.reset_active_view();
+        let active = ctx.active_console_index();
+        let cursor_keys = ctx.buffered_console(active)?.buffer().mode(Mode::CursorKeys);
+        let writer = ctx.active_console_mut()?.writer();
+        writer.write_all(&key.encode(cursor_keys)).ok();
+        writer.flush().ok();
         None
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Bindings;
+    use crate::layout::Orientation;
+    use crate::mockpty::{MockPty, MockPtyHandle, ScriptStep};
+    use crate::surface::Coord;
+    use crate::ConsoleEnabledToken;
+
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    type Handles = Rc<RefCell<Vec<MockPtyHandle>>>;
+
+    /// Host input that arrives as `chunks`, then stays open without sending anything else.
+    struct OpenInput(Vec<Vec<u8>>);
+
+    impl Read for OpenInput {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            if self.0.is_empty() {
+                loop {
+                    thread::park();
+                }
+            }
+            let chunk = self.0.remove(0);
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    /// A context with one pane whose consoles replay `script`, fed `input` as host input.
+    fn setup(script: Vec<ScriptStep>, input: &[u8]) -> (EventContext<'static, MockPty>, Handles) {
+        setup_with_input(script, Cursor::new(input.to_vec()))
+    }
+
+    fn setup_with_input<R>(
+        script: Vec<ScriptStep>,
+        input: R,
+    ) -> (EventContext<'static, MockPty>, Handles)
+    where
+        R: Read + Send + 'static,
+    {
+        let handles: Handles = Rc::new(RefCell::new(Vec::new()));
+        let factory_handles = handles.clone();
+        let mut ectx = EventContext::new(ConsoleEnabledToken);
+        ectx.console_factory(move |size| {
+            let console = MockPty::new(*size, script.clone());
+            factory_handles.borrow_mut().push(console.handle());
+            Ok(console)
+        });
+        ectx.context_mut().resize(Coord { x: 80, y: 24 });
+        ectx.context_mut().split(Orientation::Horizontal).unwrap();
+        listen_input_from(&mut ectx, input);
+        register_console_handler(&mut ectx, Bindings::default());
+        (ectx, handles)
+    }
+
+    fn step_until<F>(ectx: &mut EventContext<MockPty>, mut done: F)
+    where
+        F: FnMut(&EventContext<MockPty>) -> bool,
+    {
+        while !done(ectx) {
+            ectx.step().expect("no senders left");
+        }
+    }
+
+    fn written(handles: &Handles, idx: usize) -> Vec<u8> {
+        handles.borrow()[idx].written()
+    }
+
+    #[test]
+    fn prefix_runs_bound_command() {
+        let (mut ectx, handles) = setup(vec![], b"\x02%");
+        step_until(&mut ectx, |e| e.context().pane_rects().len() == 2);
+        assert!(handles.borrow()[1].started());
+        assert!(written(&handles, 0).is_empty());
+    }
+
+    #[test]
+    fn swap_pane_moves_active_pane_along() {
+        let (mut ectx, _) = setup(vec![], b"\x02%\x02}");
+        step_until(&mut ectx, |e| {
+            e.context().layout().map(|l| l.leaves()) == Some(vec![1, 0])
+        });
+        assert_eq!(ectx.context().active_console_index(), 1);
+    }
+
+    #[test]
+    fn prefix_twice_sends_prefix() {
+        let (mut ectx, handles) = setup(vec![], b"\x02\x02q");
+        step_until(&mut ectx, |_| written(&handles, 0).ends_with(b"q"));
+        assert_eq!(written(&handles, 0), b"\x02q");
+    }
+
+    #[test]
+    fn unbound_keys_pass_through() {
+        let (mut ectx, handles) = setup(vec![], b"ab\x1b[A\x02yc");
+        step_until(&mut ectx, |_| written(&handles, 0).ends_with(b"c"));
+        // An unbound key after the prefix is dropped rather than sent.
+        assert_eq!(written(&handles, 0), b"ab\x1b[Ac");
+    }
+
+    #[test]
+    fn lone_escape_is_sent_after_timeout() {
+        let input = OpenInput(vec![b"a\x1b".to_vec()]);
+        let (mut ectx, handles) = setup_with_input(vec![], input);
+        step_until(&mut ectx, |_| written(&handles, 0).ends_with(b"\x1b"));
+        assert_eq!(written(&handles, 0), b"a\x1b");
+    }
+
+    #[test]
+    fn unfinished_sequence_is_passed_through_after_timeout() {
+        let input = OpenInput(vec![b"\x1b[1;".to_vec()]);
+        let (mut ectx, handles) = setup_with_input(vec![], input);
+        step_until(&mut ectx, |_| !written(&handles, 0).is_empty());
+        assert_eq!(written(&handles, 0), b"\x1b[1;");
+    }
+
+    #[test]
+    fn escape_sequence_split_across_reads_is_one_key() {
+        let input = OpenInput(vec![b"\x1b".to_vec(), b"[A".to_vec(), b"b".to_vec()]);
+        let (mut ectx, handles) = setup_with_input(vec![], input);
+        step_until(&mut ectx, |_| written(&handles, 0).ends_with(b"b"));
+        assert_eq!(written(&handles, 0), b"\x1b[Ab");
+    }
+
+    #[test]
+    fn output_reaches_buffer() {
+        let script = vec![
+            ScriptStep::Output(b"hello".to_vec()),
+            ScriptStep::Delay(Duration::from_millis(10)),
+            ScriptStep::Output(b" world".to_vec()),
+        ];
+        let (mut ectx, _) = setup(script, b"");
+        step_until(&mut ectx, |e| {
+            let console = e.context().buffered_console(0).unwrap();
+            console.buffer().raw().starts_with("hello world")
+        });
+    }
+
+    #[test]
+    fn split_refuses_empty_panes() {
+        let (mut ectx, _) = setup(vec![], b"");
+        ectx.context_mut().resize(Coord { x: 3, y: 24 });
+        assert!(ectx.context_mut().split(Orientation::Horizontal).is_ok());
+        assert!(ectx.context_mut().split(Orientation::Horizontal).is_err());
+        let rects = ectx.context().pane_rects();
+        assert_eq!(rects.len(), 2);
+        assert!(rects.iter().all(|(_, r)| r.size.x > 0));
+    }
+
+    #[test]
+    fn zoom_resizes_active_pane_to_the_whole_surface() {
+        let (mut ectx, handles) = setup(vec![], b"");
+        ectx.context_mut().split(Orientation::Horizontal).unwrap();
+        let before: Vec<Coord> = ectx
+            .context()
+            .pane_rects()
+            .iter()
+            .map(|(_, r)| r.size)
+            .collect();
+        let resizes_of_left = handles.borrow()[0].resizes().len();
+
+        ectx.context_mut().toggle_zoom();
+        let area = ectx.context().pane_area().size;
+        assert_eq!(handles.borrow()[1].resizes().last(), Some(&area));
+        assert_eq!(handles.borrow()[0].resizes().len(), resizes_of_left);
+
+        ectx.context_mut().toggle_zoom();
+        for (i, size) in before.iter().enumerate() {
+            assert_eq!(handles.borrow()[i].resizes().last(), Some(size));
+        }
+    }
+
+    #[test]
+    fn tabs_are_created_and_cycled() {
+        let (mut ectx, _) = setup(vec![], b"");
+        let ctx = ectx.context_mut();
+        assert_eq!(ctx.new_tab().unwrap(), 1);
+        assert_eq!(ctx.new_tab().unwrap(), 2);
+        assert_eq!(ctx.tabs().len(), 3);
+        assert_eq!(ctx.active_console_index(), 2);
+
+        ctx.next_tab();
+        assert_eq!(ctx.active_tab_index(), 0);
+        ctx.previous_tab();
+        assert_eq!(ctx.active_tab_index(), 2);
+        ctx.previous_tab();
+        assert_eq!(ctx.active_tab_index(), 1);
+
+        assert!(ctx.select_tab(0).is_ok());
+        assert_eq!(ctx.active_tab_index(), 0);
+        assert!(ctx.select_tab(3).is_err());
+        assert_eq!(ctx.active_tab_index(), 0);
+    }
+
+    #[test]
+    fn closing_a_tab_drops_its_consoles() {
+        let (mut ectx, _) = setup(vec![], b"");
+        let ctx = ectx.context_mut();
+        ctx.new_tab().unwrap();
+        ctx.split(Orientation::Vertical).unwrap();
+        ctx.new_tab().unwrap();
+        ctx.select_tab(1).unwrap();
+
+        ctx.close_tab(1);
+        assert_eq!(ctx.tabs().len(), 2);
+        assert!(ctx.buffered_console(1).is_none());
+        assert!(ctx.buffered_console(2).is_none());
+        // The tab after the closed one takes its place.
+        assert_eq!(ctx.active_tab_index(), 1);
+        assert_eq!(ctx.active_console_index(), 3);
+
+        ctx.close_tab(1);
+        assert_eq!(ctx.active_tab_index(), 0);
+        assert_eq!(ctx.active_console_index(), 0);
+    }
+
+    #[test]
+    fn rename_tab_prompt() {
+        let (mut ectx, handles) = setup(vec![], b"\x02,wokr\x7f\x7frk\rx");
+        step_until(&mut ectx, |_| written(&handles, 0).ends_with(b"x"));
+        assert_eq!(ectx.context().tab_name(0), "work");
+        assert_eq!(written(&handles, 0), b"x");
+
+        execute(ectx.context_mut(), Command::RenameTab(Some("logs".into()))).unwrap();
+        assert!(ectx.context().status().starts_with("[conmux] 0:logs*"));
+    }
+
+    #[test]
+    fn kill_server_stops_the_event_loop() {
+        let (mut ectx, _) = setup(vec![], b"\x02\\");
+        ectx.context_mut().new_tab().unwrap();
+        loop {
+            if let Some(Action::Quit) = ectx.step() {
+                break;
+            }
+        }
+        assert!(ectx.context().tabs().is_empty());
+        assert!(ectx.context().is_empty());
+    }
+
+    #[test]
+    fn dragging_a_border_resizes_both_panes_on_release() {
+        // Press on the border column, drag it to column 21, let go there.
+        let input = b"\x1b[<0;41;5M\x1b[<32;30;5M\x1b[<32;21;5M\x1b[<0;21;5m";
+        let (mut ectx, handles) = setup(vec![], input);
+        ectx.context_mut().split(Orientation::Horizontal).unwrap();
+        crate::mouse::register_mouse_handler(&mut ectx);
+        step_until(&mut ectx, |_| {
+            handles.borrow()[0].resizes().last() == Some(&Coord { x: 20, y: 23 })
+        });
+        assert_eq!(
+            handles.borrow()[1].resizes().last(),
+            Some(&Coord { x: 59, y: 23 })
+        );
+        let sizes: Vec<Coord> = ectx
+            .context()
+            .pane_rects()
+            .iter()
+            .map(|(_, r)| r.size)
+            .collect();
+        assert_eq!(sizes, vec![Coord { x: 20, y: 23 }, Coord { x: 59, y: 23 }]);
+    }
+
+    fn set_bracketed_paste(ectx: &mut EventContext<MockPty>, idx: usize) {
+        let console = ectx.context_mut().buffered_console_mut(idx).unwrap();
+        console.buffer_mut().set_mode(Mode::BracketedPaste);
+    }
+
+    #[test]
+    fn host_paste_keeps_brackets_only_for_panes_that_asked() {
+        let input = b"\x1b[200~plain\x1b[201~\x02o\x1b[200~brackets\x1b[201~";
+        let (mut ectx, handles) = setup(vec![], input);
+        ectx.context_mut().split(Orientation::Horizontal).unwrap();
+        set_bracketed_paste(&mut ectx, 0);
+
+        step_until(&mut ectx, |_| written(&handles, 0).ends_with(PASTE_END));
+        assert_eq!(written(&handles, 0), b"\x1b[200~brackets\x1b[201~");
+        assert_eq!(written(&handles, 1), b"plain");
+    }
+
+    #[test]
+    fn paste_cannot_end_the_brackets_early() {
+        let (mut ectx, handles) = setup(vec![], b"");
+        set_bracketed_paste(&mut ectx, 0);
+        ectx.context_mut().paste(b"a\x1b[201~\rrm -rf ~\r").unwrap();
+        assert_eq!(written(&handles, 0), b"\x1b[200~a\rrm -rf ~\r\x1b[201~");
+    }
+
+    #[test]
+    fn focus_is_reported_only_to_panes_that_asked() {
+        // Focus the left pane, blur and refocus the host, go back to the right pane and do it again.
+        let input = b"\x02o\x1b[O\x1b[I\x02o\x1b[O\x1b[Iend";
+        let (mut ectx, handles) = setup(vec![], input);
+        ectx.context_mut().split(Orientation::Horizontal).unwrap();
+        ectx.context_mut()
+            .buffered_console_mut(0)
+            .unwrap()
+            .buffer_mut()
+            .set_mode(Mode::ReportFocusInOut);
+
+        step_until(&mut ectx, |_| written(&handles, 1).ends_with(b"end"));
+        assert_eq!(written(&handles, 0), b"\x1b[I\x1b[O\x1b[I\x1b[O");
+        assert_eq!(written(&handles, 1), b"end");
+    }
+
+    #[test]
+    fn resize_and_kill_are_seen() {
+        let (mut ectx, handles) = setup(vec![], b"");
+        ectx.context_mut().resize(Coord { x: 100, y: 30 });
+        let size = ectx.context().pane_rects()[0].1.size;
+        assert_eq!(handles.borrow()[0].resizes().last(), Some(&size));
+
+        handles.borrow()[0].kill();
+        loop {
+            if let Some(Action::PtyDead(0)) = ectx.step() {
+                break;
+            }
+        }
+    }
+}
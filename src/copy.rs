@@ -0,0 +1,778 @@
+use crate::buffer::{Buffer, Cell, Row};
+use crate::keys::{Key, KeyCode};
+use crate::search::{find_matches, Pattern, Search};
+
+/// The keys copy mode answers to, like the `mode-keys` option of tmux.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModeKeys {
+    Vi,
+    Emacs,
+}
+
+impl ModeKeys {
+    pub fn parse(name: &str) -> Option<ModeKeys> {
+        match name {
+            "vi" => Some(ModeKeys::Vi),
+            "emacs" => Some(ModeKeys::Emacs),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectionKind {
+    /// Runs of text from one character to another, following the lines between
+    Char,
+    /// Whole lines
+    Line,
+    /// The same columns on every line
+    Rect,
+}
+
+/// A cell in the history of a buffer.
+///
+/// Lines are counted from the first line the buffer ever had, so points stay on the same
+/// text while the scrollback is trimmed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Point {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// What the pane should do after a key was handled in copy mode.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CopyAction {
+    Continue,
+    Exit,
+    /// Leave copy mode, keeping the selected text
+    Yank(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    LineStart,
+    LineEnd,
+    FirstNonBlank,
+    NextWord,
+    PreviousWord,
+    WordEnd,
+    PageUp,
+    PageDown,
+    HalfPageUp,
+    HalfPageDown,
+    Top,
+    Bottom,
+    ScreenTop,
+    ScreenMiddle,
+    ScreenBottom,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Move(Motion),
+    Select(SelectionKind),
+    ToggleRect,
+    ClearSelection,
+    /// Opens the search prompt, searching forward if true
+    Search(bool),
+    /// Moves to the next match of the last search, the other way if true
+    SearchAgain(bool),
+    Yank,
+    Exit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CharClass {
+    Blank,
+    Word,
+    Punctuation,
+}
+
+fn class(c: char) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Blank
+    } else if c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+/// A search query being typed.
+struct Prompt {
+    query: String,
+    forward: bool,
+    regex: bool,
+}
+
+/// Moving a cursor over the history of a pane to select and copy text from it.
+///
+/// The view of the pane is frozen while copy mode is on, output carries on into the
+/// buffer underneath without moving the lines on view.
+pub struct CopyMode {
+    keys: ModeKeys,
+    cursor: Point,
+    /// Where the selection started, along with how it is shaped
+    selection: Option<(Point, SelectionKind)>,
+    prompt: Option<Prompt>,
+    search: Option<Search>,
+    /// Shown in the status line until the next key
+    message: Option<String>,
+}
+
+impl CopyMode {
+    /// Starts copy mode on a buffer with the cursor where the console's cursor is on view.
+    pub fn new(buffer: &mut Buffer, keys: ModeKeys) -> CopyMode {
+        buffer.freeze_view(true);
+        CopyMode {
+            keys,
+            cursor: Point {
+                line: buffer.first_line() + buffer.view_start() + buffer.cursor().y,
+                column: buffer.cursor().x,
+            },
+            selection: None,
+            prompt: None,
+            search: None,
+            message: None,
+        }
+    }
+
+    /// Lets the view of the buffer follow its output again.
+    pub fn exit(&self, buffer: &mut Buffer) {
+        buffer.freeze_view(false);
+        buffer.reset_view();
+    }
+
+    pub fn cursor(&self) -> Point {
+        self.cursor
+    }
+
+    /// The last search, for its matches to be highlighted.
+    pub fn search(&self) -> Option<&Search> {
+        self.search.as_ref()
+    }
+
+    /// Opens the search prompt.
+    pub fn start_search(&mut self, forward: bool) {
+        self.prompt = Some(Prompt {
+            query: String::new(),
+            forward,
+            regex: false,
+        });
+    }
+
+    /// What copy mode shows in the status line, the search prompt while a query is typed.
+    pub fn status(&self) -> String {
+        if let Some(prompt) = self.prompt.as_ref() {
+            return format!(
+                "[copy] (search {}{}) {}",
+                if prompt.forward { "down" } else { "up" },
+                if prompt.regex { ", regex" } else { "" },
+                prompt.query
+            );
+        }
+        if let Some(message) = self.message.as_ref() {
+            return format!("[copy] {}", message);
+        }
+        match self.search.as_ref() {
+            Some(search) => match search.position() {
+                Some(idx) => format!("[copy] [{}/{}]", idx + 1, search.matches().len()),
+                None => format!("[copy] [{}]", search.matches().len()),
+            },
+            None => String::from("[copy]"),
+        }
+    }
+
+    /// Whether a cell is inside the selection.
+    pub fn selected(&self, point: Point) -> bool {
+        let (anchor, kind) = match self.selection {
+            Some(selection) => selection,
+            None => return false,
+        };
+        let (start, end) = (anchor.min(self.cursor), anchor.max(self.cursor));
+
+        match kind {
+            SelectionKind::Char => start <= point && point <= end,
+            SelectionKind::Line => start.line <= point.line && point.line <= end.line,
+            SelectionKind::Rect => {
+                let (left, right) = columns(anchor, self.cursor);
+                start.line <= point.line
+                    && point.line <= end.line
+                    && left <= point.column
+                    && point.column <= right
+            }
+        }
+    }
+
+    /// The text in the selection, lines wrapped by the console are joined back together.
+    pub fn selected_text(&self, buffer: &Buffer) -> Option<String> {
+        let (anchor, kind) = self.selection?;
+        let (start, end) = (anchor.min(self.cursor), anchor.max(self.cursor));
+        let width = buffer.dimensions().x;
+        let (left, right) = columns(anchor, self.cursor);
+
+        let mut text = String::new();
+        for line in start.line..=end.line {
+            let row = match row(buffer, line) {
+                Some(row) => row,
+                None => continue,
+            };
+
+            let (from, to) = match kind {
+                SelectionKind::Char if line == start.line && line == end.line => {
+                    (start.column, end.column)
+                }
+                SelectionKind::Char if line == start.line => (start.column, width),
+                SelectionKind::Char if line == end.line => (0, end.column),
+                SelectionKind::Char | SelectionKind::Line => (0, width),
+                SelectionKind::Rect => (left, right),
+            };
+
+            let cells = row.cells();
+            let part: String = cells[from.min(cells.len())..(to + 1).min(cells.len())]
+                .iter()
+                .map(|c| c.text())
+                .collect();
+
+            // A wrapped row runs straight on into the next one, its trailing blanks are text.
+            let joined = kind != SelectionKind::Rect && row.wrapped() && line != end.line;
+            if joined {
+                text.push_str(&part);
+            } else {
+                text.push_str(part.trim_end());
+                if line != end.line {
+                    text.push('\n');
+                }
+            }
+        }
+        Some(text)
+    }
+
+    /// Handles a key pressed while the pane is in copy mode.
+    pub fn key(&mut self, key: Key, buffer: &mut Buffer) -> CopyAction {
+        self.message = None;
+        if self.prompt.is_some() {
+            self.prompt_key(key, buffer);
+            return CopyAction::Continue;
+        }
+
+        let op = match self.keys {
+            ModeKeys::Vi => vi_op(key),
+            ModeKeys::Emacs => emacs_op(key),
+        };
+
+        self.clamp(buffer);
+        match op {
+            Some(Op::Move(motion)) => self.move_cursor(motion, buffer),
+            Some(Op::Select(kind)) => {
+                self.selection = match self.selection {
+                    // Selecting the same way again turns the selection off, like vim.
+                    Some((_, k)) if k == kind && self.keys == ModeKeys::Vi => None,
+                    Some((anchor, _)) if self.keys == ModeKeys::Vi => Some((anchor, kind)),
+                    _ => Some((self.cursor, kind)),
+                }
+            }
+            Some(Op::ToggleRect) => {
+                self.selection = self.selection.map(|(anchor, kind)| match kind {
+                    SelectionKind::Rect => (anchor, SelectionKind::Char),
+                    _ => (anchor, SelectionKind::Rect),
+                })
+            }
+            Some(Op::ClearSelection) => {
+                if self.selection.take().is_none() {
+                    return CopyAction::Exit;
+                }
+            }
+            Some(Op::Yank) => {
+                return match self.selected_text(buffer) {
+                    Some(text) => CopyAction::Yank(text),
+                    None => CopyAction::Exit,
+                }
+            }
+            Some(Op::Search(forward)) => self.start_search(forward),
+            Some(Op::SearchAgain(reverse)) => self.next_match(reverse),
+            Some(Op::Exit) => return CopyAction::Exit,
+            None => (),
+        }
+        self.scroll_to_cursor(buffer);
+        CopyAction::Continue
+    }
+
+    /// Edits the search query, running the search on Enter. Tab switches to a regex search.
+    fn prompt_key(&mut self, key: Key, buffer: &mut Buffer) {
+        let prompt = match self.prompt.as_mut() {
+            Some(prompt) => prompt,
+            None => return,
+        };
+
+        match (key.code, key.modifiers.ctrl) {
+            (KeyCode::Enter, _) => {
+                if let Some(prompt) = self.prompt.take() {
+                    self.run_search(prompt, buffer);
+                }
+            }
+            (KeyCode::Escape, _) | (KeyCode::Char('g'), true) | (KeyCode::Char('c'), true) => {
+                self.prompt = None
+            }
+            (KeyCode::Backspace, _) => {
+                prompt.query.pop();
+            }
+            (KeyCode::Tab, _) => prompt.regex = !prompt.regex,
+            (KeyCode::Char('u'), true) => prompt.query.clear(),
+            (KeyCode::Char(c), false) if !key.modifiers.alt => prompt.query.push(c),
+            _ => (),
+        }
+    }
+
+    fn run_search(&mut self, prompt: Prompt, buffer: &mut Buffer) {
+        let pattern = match Pattern::new(&prompt.query, prompt.regex) {
+            Ok(pattern) => pattern,
+            Err(e) => {
+                self.message = Some(format!("invalid regex: {}", e));
+                return;
+            }
+        };
+
+        let matches = find_matches(buffer, &pattern);
+        self.search = Some(Search::new(prompt.query, prompt.forward, matches));
+        self.next_match(false);
+        self.scroll_to_cursor(buffer);
+    }
+
+    /// Moves the cursor to the next match of the last search.
+    fn next_match(&mut self, reverse: bool) {
+        let search = match self.search.as_mut() {
+            Some(search) => search,
+            None => return,
+        };
+        match search.next(self.cursor, reverse) {
+            Some(m) => self.cursor = m.start,
+            None => self.message = Some(format!("no matches for `{}`", search.query)),
+        }
+    }
+
+    /// Keeps the cursor on lines the buffer still has and inside its width.
+    fn clamp(&mut self, buffer: &Buffer) {
+        let first = buffer.first_line();
+        let last = (first + buffer.line_count()).saturating_sub(1);
+        self.cursor.line = self.cursor.line.max(first).min(last);
+        self.cursor.column = self.cursor.column.min(buffer.dimensions().x.saturating_sub(1));
+        if let Some((anchor, _)) = self.selection.as_mut() {
+            anchor.line = anchor.line.max(first).min(last);
+        }
+    }
+
+    fn move_cursor(&mut self, motion: Motion, buffer: &mut Buffer) {
+        let first = buffer.first_line();
+        let last = (first + buffer.line_count()).saturating_sub(1);
+        let rows = buffer.dimensions().y;
+        let top = first + buffer.view_start();
+        let width = buffer.dimensions().x;
+
+        match motion {
+            Motion::Left => self.cursor.column = self.cursor.column.saturating_sub(1),
+            Motion::Right => {
+                let mut column = self.cursor.column + 1;
+                let next = Point {
+                    column,
+                    ..self.cursor
+                };
+                if cell(buffer, next).map_or(false, |c| c.is_spacer()) {
+                    column += 1;
+                }
+                self.cursor.column = column.min(width.saturating_sub(1))
+            }
+            Motion::Up => self.cursor.line = self.cursor.line.saturating_sub(1).max(first),
+            Motion::Down => self.cursor.line = (self.cursor.line + 1).min(last),
+            Motion::LineStart => self.cursor.column = 0,
+            Motion::LineEnd => {
+                self.cursor.column = row(buffer, self.cursor.line)
+                    .and_then(|row| row.cells().iter().rposition(|c| !c.is_empty()))
+                    .unwrap_or(0)
+            }
+            Motion::FirstNonBlank => {
+                self.cursor.column = row(buffer, self.cursor.line)
+                    .and_then(|row| {
+                        row.cells()
+                            .iter()
+                            .position(|c| class(char_of(&c.text())) != CharClass::Blank)
+                    })
+                    .unwrap_or(0)
+            }
+            Motion::NextWord => self.next_word(buffer),
+            Motion::PreviousWord => self.previous_word(buffer),
+            Motion::WordEnd => self.word_end(buffer),
+            Motion::PageUp => self.page(rows as isize, buffer),
+            Motion::PageDown => self.page(-(rows as isize), buffer),
+            Motion::HalfPageUp => self.page((rows / 2) as isize, buffer),
+            Motion::HalfPageDown => self.page(-((rows / 2) as isize), buffer),
+            Motion::Top => {
+                self.cursor = Point {
+                    line: first,
+                    column: 0,
+                }
+            }
+            Motion::Bottom => {
+                self.cursor.line = last;
+                self.cursor.column = 0;
+            }
+            Motion::ScreenTop => self.cursor.line = top,
+            Motion::ScreenMiddle => self.cursor.line = (top + rows / 2).min(last),
+            Motion::ScreenBottom => self.cursor.line = (top + rows).saturating_sub(1).min(last),
+        }
+
+        // The cursor goes on the left half of a wide character, never on its spacer.
+        if cell(buffer, self.cursor).map_or(false, |c| c.is_spacer()) {
+            self.cursor.column = self.cursor.column.saturating_sub(1);
+        }
+    }
+
+    /// Scrolls the view and the cursor along with it, back into the history if `lines` is positive.
+    fn page(&mut self, lines: isize, buffer: &mut Buffer) {
+        let first = buffer.first_line() as isize;
+        let last = (buffer.first_line() + buffer.line_count()).saturating_sub(1) as isize;
+        buffer.scroll_view(lines);
+        self.cursor.line = (self.cursor.line as isize - lines).max(first).min(last) as usize;
+    }
+
+    /// Scrolls the view so the cursor is on it.
+    fn scroll_to_cursor(&self, buffer: &mut Buffer) {
+        let top = buffer.first_line() + buffer.view_start();
+        let rows = buffer.dimensions().y;
+        if self.cursor.line < top {
+            buffer.scroll_view((top - self.cursor.line) as isize);
+        } else if self.cursor.line >= top + rows {
+            buffer.scroll_view(-((self.cursor.line + 1 - top - rows) as isize));
+        }
+    }
+
+    /// Moves to the start of the next word, `w` in vi.
+    fn next_word(&mut self, buffer: &Buffer) {
+        let mut point = self.cursor;
+        let class = class_at(buffer, point);
+        if class != CharClass::Blank {
+            while let Some(next) = step_forward(buffer, point) {
+                let same = next.line == point.line && class_at(buffer, next) == class;
+                point = next;
+                if !same {
+                    break;
+                }
+            }
+        }
+        while class_at(buffer, point) == CharClass::Blank {
+            match step_forward(buffer, point) {
+                Some(next) => point = next,
+                None => break,
+            }
+        }
+        self.cursor = point;
+    }
+
+    /// Moves to the start of this word or the one before, `b` in vi.
+    fn previous_word(&mut self, buffer: &Buffer) {
+        let mut point = match step_backward(buffer, self.cursor) {
+            Some(point) => point,
+            None => return,
+        };
+        while class_at(buffer, point) == CharClass::Blank {
+            match step_backward(buffer, point) {
+                Some(previous) => point = previous,
+                None => break,
+            }
+        }
+        let class = class_at(buffer, point);
+        while let Some(previous) = step_backward(buffer, point) {
+            if previous.line != point.line || class_at(buffer, previous) != class {
+                break;
+            }
+            point = previous;
+        }
+        self.cursor = point;
+    }
+
+    /// Moves to the end of this word or the next, `e` in vi.
+    fn word_end(&mut self, buffer: &Buffer) {
+        let mut point = match step_forward(buffer, self.cursor) {
+            Some(point) => point,
+            None => return,
+        };
+        while class_at(buffer, point) == CharClass::Blank {
+            match step_forward(buffer, point) {
+                Some(next) => point = next,
+                None => break,
+            }
+        }
+        let class = class_at(buffer, point);
+        while let Some(next) = step_forward(buffer, point) {
+            if next.line != point.line || class_at(buffer, next) != class {
+                break;
+            }
+            point = next;
+        }
+        self.cursor = point;
+    }
+}
+
+/// The left and right columns of a rectangle between two points.
+fn columns(a: Point, b: Point) -> (usize, usize) {
+    (a.column.min(b.column), a.column.max(b.column))
+}
+
+fn row(buffer: &Buffer, line: usize) -> Option<&Row> {
+    buffer.line(line.checked_sub(buffer.first_line())?)
+}
+
+fn char_of(text: &str) -> char {
+    text.chars().next().unwrap_or(' ')
+}
+
+fn cell(buffer: &Buffer, point: Point) -> Option<&Cell> {
+    row(buffer, point.line)?.cells().get(point.column)
+}
+
+/// The class of the character at a point, a spacer taking that of the wide character before it.
+fn class_at(buffer: &Buffer, point: Point) -> CharClass {
+    let cell = match cell(buffer, point) {
+        Some(c) if c.is_spacer() && point.column > 0 => cell(
+            buffer,
+            Point {
+                column: point.column - 1,
+                ..point
+            },
+        ),
+        c => c,
+    };
+    cell.map_or(CharClass::Blank, |cell| class(char_of(&cell.text())))
+}
+
+fn step_forward(buffer: &Buffer, point: Point) -> Option<Point> {
+    if point.column + 1 < buffer.dimensions().x {
+        Some(Point {
+            column: point.column + 1,
+            ..point
+        })
+    } else if point.line + 1 < buffer.first_line() + buffer.line_count() {
+        Some(Point {
+            line: point.line + 1,
+            column: 0,
+        })
+    } else {
+        None
+    }
+}
+
+fn step_backward(buffer: &Buffer, point: Point) -> Option<Point> {
+    if point.column > 0 {
+        Some(Point {
+            column: point.column - 1,
+            ..point
+        })
+    } else if point.line > buffer.first_line() {
+        Some(Point {
+            line: point.line - 1,
+            column: buffer.dimensions().x.saturating_sub(1),
+        })
+    } else {
+        None
+    }
+}
+
+fn vi_op(key: Key) -> Option<Op> {
+    let op = match (key.code, key.modifiers.ctrl, key.modifiers.alt) {
+        (KeyCode::Char('h'), false, false) | (KeyCode::Left, _, _) | (KeyCode::Backspace, _, _) => {
+            Op::Move(Motion::Left)
+        }
+        (KeyCode::Char('l'), false, false) | (KeyCode::Right, _, _) => Op::Move(Motion::Right),
+        (KeyCode::Char('k'), false, false) | (KeyCode::Up, _, _) => Op::Move(Motion::Up),
+        (KeyCode::Char('j'), false, false) | (KeyCode::Down, _, _) => Op::Move(Motion::Down),
+        (KeyCode::Char('0'), false, false) | (KeyCode::Home, _, _) => Op::Move(Motion::LineStart),
+        (KeyCode::Char('$'), false, false) | (KeyCode::End, _, _) => Op::Move(Motion::LineEnd),
+        (KeyCode::Char('^'), false, false) => Op::Move(Motion::FirstNonBlank),
+        (KeyCode::Char('w'), false, false) => Op::Move(Motion::NextWord),
+        (KeyCode::Char('b'), false, false) => Op::Move(Motion::PreviousWord),
+        (KeyCode::Char('e'), false, false) => Op::Move(Motion::WordEnd),
+        (KeyCode::Char('b'), true, false) | (KeyCode::PageUp, _, _) => Op::Move(Motion::PageUp),
+        (KeyCode::Char('f'), true, false) | (KeyCode::PageDown, _, _) => {
+            Op::Move(Motion::PageDown)
+        }
+        (KeyCode::Char('u'), true, false) => Op::Move(Motion::HalfPageUp),
+        (KeyCode::Char('d'), true, false) => Op::Move(Motion::HalfPageDown),
+        (KeyCode::Char('g'), false, false) => Op::Move(Motion::Top),
+        (KeyCode::Char('G'), false, false) => Op::Move(Motion::Bottom),
+        (KeyCode::Char('H'), false, false) => Op::Move(Motion::ScreenTop),
+        (KeyCode::Char('M'), false, false) => Op::Move(Motion::ScreenMiddle),
+        (KeyCode::Char('L'), false, false) => Op::Move(Motion::ScreenBottom),
+        (KeyCode::Char('v'), false, false) | (KeyCode::Char(' '), false, false) => {
+            Op::Select(SelectionKind::Char)
+        }
+        (KeyCode::Char('V'), false, false) => Op::Select(SelectionKind::Line),
+        (KeyCode::Char('v'), true, false) => Op::Select(SelectionKind::Rect),
+        (KeyCode::Escape, _, _) => Op::ClearSelection,
+        (KeyCode::Enter, _, _) | (KeyCode::Char('y'), false, false) => Op::Yank,
+        (KeyCode::Char('/'), false, false) => Op::Search(true),
+        (KeyCode::Char('?'), false, false) => Op::Search(false),
+        (KeyCode::Char('n'), false, false) => Op::SearchAgain(false),
+        (KeyCode::Char('N'), false, false) => Op::SearchAgain(true),
+        (KeyCode::Char('q'), false, false) | (KeyCode::Char('c'), true, false) => Op::Exit,
+        _ => return None,
+    };
+    Some(op)
+}
+
+fn emacs_op(key: Key) -> Option<Op> {
+    let op = match (key.code, key.modifiers.ctrl, key.modifiers.alt) {
+        (KeyCode::Char('b'), true, false) | (KeyCode::Left, _, _) => Op::Move(Motion::Left),
+        (KeyCode::Char('f'), true, false) | (KeyCode::Right, _, _) => Op::Move(Motion::Right),
+        (KeyCode::Char('p'), true, false) | (KeyCode::Up, _, _) => Op::Move(Motion::Up),
+        (KeyCode::Char('n'), true, false) | (KeyCode::Down, _, _) => Op::Move(Motion::Down),
+        (KeyCode::Char('a'), true, false) | (KeyCode::Home, _, _) => Op::Move(Motion::LineStart),
+        (KeyCode::Char('e'), true, false) | (KeyCode::End, _, _) => Op::Move(Motion::LineEnd),
+        (KeyCode::Char('m'), false, true) => Op::Move(Motion::FirstNonBlank),
+        (KeyCode::Char('f'), false, true) => Op::Move(Motion::WordEnd),
+        (KeyCode::Char('b'), false, true) => Op::Move(Motion::PreviousWord),
+        (KeyCode::Char('v'), false, true) | (KeyCode::PageUp, _, _) => Op::Move(Motion::PageUp),
+        (KeyCode::Char('v'), true, false) | (KeyCode::PageDown, _, _) => {
+            Op::Move(Motion::PageDown)
+        }
+        (KeyCode::Char('<'), false, true) => Op::Move(Motion::Top),
+        (KeyCode::Char('>'), false, true) => Op::Move(Motion::Bottom),
+        (KeyCode::Char(' '), true, false) => Op::Select(SelectionKind::Char),
+        (KeyCode::Char('l'), false, true) => Op::Select(SelectionKind::Line),
+        (KeyCode::Char('R'), false, false) => Op::ToggleRect,
+        (KeyCode::Char('s'), true, false) => Op::Search(true),
+        (KeyCode::Char('r'), true, false) => Op::Search(false),
+        (KeyCode::Char('n'), false, false) => Op::SearchAgain(false),
+        (KeyCode::Char('N'), false, false) => Op::SearchAgain(true),
+        (KeyCode::Char('g'), true, false) => Op::ClearSelection,
+        (KeyCode::Char('w'), _, _) if key.modifiers.ctrl || key.modifiers.alt => Op::Yank,
+        (KeyCode::Char('q'), false, false) | (KeyCode::Escape, _, _) => Op::Exit,
+        _ => return None,
+    };
+    Some(op)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::parse;
+    use crate::surface::Coord;
+
+    /// A buffer showing `text`, with the cursor back in the top left corner.
+    fn buffer(width: usize, rows: usize, text: &[u8]) -> Buffer {
+        let mut buffer = Buffer::new(Coord { x: width, y: rows });
+        for action in parse(text) {
+            buffer.perform(&action);
+        }
+        buffer.goto(0, 0);
+        buffer
+    }
+
+    /// Presses each key of a space separated list, returning what the last one did.
+    fn press(copy: &mut CopyMode, buffer: &mut Buffer, keys: &str) -> CopyAction {
+        let mut action = CopyAction::Continue;
+        for key in keys.split(' ') {
+            action = copy.key(Key::parse(key).unwrap(), buffer);
+        }
+        action
+    }
+
+    fn at(line: usize, column: usize) -> Point {
+        Point { line, column }
+    }
+
+    #[test]
+    fn vi_motions() {
+        let mut buffer = buffer(20, 3, b"  foo bar.baz  qux\r\nnext line");
+        let mut copy = CopyMode::new(&mut buffer, ModeKeys::Vi);
+        let mut expect = |keys: &str, point: Point| {
+            press(&mut copy, &mut buffer, keys);
+            assert_eq!(copy.cursor(), point, "after {}", keys);
+        };
+
+        expect("w", at(0, 2));
+        expect("w", at(0, 6));
+        expect("w", at(0, 9));
+        expect("w", at(0, 10));
+        expect("w", at(0, 15));
+        expect("e", at(0, 17));
+        expect("b", at(0, 15));
+        expect("w", at(1, 0));
+        expect("k", at(0, 0));
+        expect("^", at(0, 2));
+        expect("$", at(0, 17));
+        expect("0", at(0, 0));
+        expect("l l h", at(0, 1));
+        expect("G", at(2, 0));
+        expect("g", at(0, 0));
+    }
+
+    #[test]
+    fn emacs_motions() {
+        let mut buffer = buffer(20, 3, b"  foo bar\r\nnext line");
+        let mut copy = CopyMode::new(&mut buffer, ModeKeys::Emacs);
+        let mut expect = |keys: &str, point: Point| {
+            press(&mut copy, &mut buffer, keys);
+            assert_eq!(copy.cursor(), point, "after {}", keys);
+        };
+
+        expect("M-m", at(0, 2));
+        expect("M-f", at(0, 4));
+        expect("M-f", at(0, 8));
+        expect("M-b", at(0, 6));
+        expect("C-f C-f C-b", at(0, 7));
+        expect("C-e", at(0, 8));
+        expect("C-a", at(0, 0));
+        expect("C-n", at(1, 0));
+        expect("C-p", at(0, 0));
+        expect("M->", at(2, 0));
+        expect("M-<", at(0, 0));
+    }
+
+    #[test]
+    fn selections() {
+        let text = b"abcdef\r\nghijkl\r\nmnopqr";
+
+        let mut buf = buffer(10, 3, text);
+        let mut copy = CopyMode::new(&mut buf, ModeKeys::Vi);
+        press(&mut copy, &mut buf, "l l v j");
+        assert!(copy.selected(at(0, 5)) && copy.selected(at(1, 0)));
+        assert!(!copy.selected(at(0, 1)) && !copy.selected(at(1, 3)));
+        let yanked = press(&mut copy, &mut buf, "y");
+        assert_eq!(yanked, CopyAction::Yank(String::from("cdef\nghi")));
+
+        let mut buf = buffer(10, 3, text);
+        let mut copy = CopyMode::new(&mut buf, ModeKeys::Vi);
+        press(&mut copy, &mut buf, "l l V j");
+        assert_eq!(copy.selected_text(&buf).unwrap(), "abcdef\nghijkl");
+
+        let mut buf = buffer(10, 3, text);
+        let mut copy = CopyMode::new(&mut buf, ModeKeys::Vi);
+        press(&mut copy, &mut buf, "l C-v j j l l");
+        assert!(copy.selected(at(1, 2)) && !copy.selected(at(1, 0)));
+        assert_eq!(copy.selected_text(&buf).unwrap(), "bcd\nhij\nnop");
+
+        let mut buf = buffer(10, 3, text);
+        let mut copy = CopyMode::new(&mut buf, ModeKeys::Emacs);
+        press(&mut copy, &mut buf, "C-f C-Space C-n C-n C-f C-f R");
+        assert_eq!(copy.selected_text(&buf).unwrap(), "bcd\nhij\nnop");
+        press(&mut copy, &mut buf, "R");
+        assert_eq!(copy.selected_text(&buf).unwrap(), "bcdef\nghijkl\nmnop");
+    }
+
+    #[test]
+    fn wrapped_rows_are_joined() {
+        let mut buf = buffer(5, 3, b"ab   cdef\r\nij");
+        assert!(buf.rows()[0].wrapped() && !buf.rows()[1].wrapped());
+
+        let mut copy = CopyMode::new(&mut buf, ModeKeys::Vi);
+        press(&mut copy, &mut buf, "V j j");
+        assert_eq!(copy.selected_text(&buf).unwrap(), "ab   cdef\nij");
+
+        press(&mut copy, &mut buf, "v k l");
+        assert_eq!(copy.selected_text(&buf).unwrap(), "ab   cd");
+    }
+}
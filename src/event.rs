@@ -1,20 +1,42 @@
 use crate::pty::*;
+#[cfg(unix)]
+use crate::unixcon::ConsoleEnabledToken;
+#[cfg(windows)]
 use crate::wincon::ConsoleEnabledToken;
-use crate::surface::Coord;
+use crate::surface::{Coord, Rect};
 use crate::ansitypes::*;
+use crate::buffer::DEFAULT_SCROLLBACK_LIMIT;
+use crate::compositor::Pane;
+use crate::copy::{CopyAction, CopyMode, ModeKeys};
+use crate::paste::{BufferChooser, ChooserAction, PasteBuffers};
+use crate::keys::{Key, KeyCode};
+use crate::mouse::MouseEvent;
+use crate::layout::{neighbour, split_rect, Direction, Layout, Orientation};
+use crate::tabs::Tab;
+use crate::output::OutputPerformer;
 
 use crossbeam::channel::*;
 
+use std::collections::HashMap;
 use std::io::{stdin, Read, Write};
 use std::io::{Error, ErrorKind, Result};
-use std::marker::PhantomData;
 
 use std::sync::{Arc, Weak};
 use std::process::exit;
-use std::ops::FnOnce;
+use std::ops::{FnOnce, Range};
 use std::thread::{self as thread, JoinHandle};
+use std::time::Duration;
+use vte::Parser;
 
-#[derive(Debug, Copy, Clone)]
+/// How often a console is checked for its shell having exited.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Sent before pasted text to consoles with bracketed paste turned on.
+pub const PASTE_START: &[u8] = b"\x1b[200~";
+/// Sent after pasted text to consoles with bracketed paste turned on.
+pub const PASTE_END: &[u8] = b"\x1b[201~";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct PtyIndex(pub usize);
 #[derive(Debug, Copy, Clone)]
 pub struct Line(pub usize);
@@ -22,15 +44,16 @@ pub struct Line(pub usize);
 pub struct Column(pub usize);
 
 #[allow(unused)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum Action {
     KeyInputReceived(u8),
-    HostInput(char),
-    HostControlInput(u8),
-    HostDeleteKey,
-    HostInsertKey,
-    HostPageUpKey,
-    HostPageDownKey,
+    HostKey(Key),
+    /// Input sequence conmux doesn't handle, to be sent to the active console as is
+    HostPassthrough(Vec<u8>),
+    /// Text pasted into the host terminal, without its paste brackets
+    HostPaste(Vec<u8>),
+    /// The host terminal gained or lost focus
+    HostFocus(bool),
     HostInsertBlank(Column),
     HostPutTabs(i64),
     HostBackspace,
@@ -46,7 +69,7 @@ pub enum Action {
     HostClearTabs(TabulationClearMode),
     HostSubtitute,
     
-    HostMouseInputReceived(u8),
+    HostMouse(MouseEvent),
     HostCursorGoto(Line, Column),
     HostCursorMoveUp(Line),
     HostCursorMoveDown(Line),
@@ -61,6 +84,9 @@ pub enum Action {
     
     PtyOutReceived(usize, u8),
 
+    HostResize(Coord),
+    Redraw,
+
     // rename these (not actually PTY)
     PtyActiveChange(usize),
     PtyDead(usize),
@@ -95,6 +121,8 @@ pub enum Action {
 
     // Cursor manipulation
     PtyCursorGoto(PtyIndex, Line, Column),
+    PtyCursorGotoLine(PtyIndex, Line),
+    PtyCursorGotoColumn(PtyIndex, Column),
     PtyCursorMoveUp(PtyIndex, Line),
     PtyCursorMoveDown(PtyIndex, Line),
     PtyCursorMoveForward(PtyIndex, Column),
@@ -112,30 +140,118 @@ pub enum Action {
     PtyUnsetKeypadApplicationMode(PtyIndex),
     PtySetActiveCharset(PtyIndex, CharsetIndex),
     PtyConfigureCharset(PtyIndex, CharsetIndex, StandardCharset),
+    /// Uses a character set for the next character printed only
+    PtySingleShift(PtyIndex, CharsetIndex),
     PtySetColor(PtyIndex, usize, (u8, u8, u8)),
     PtyResetColor(PtyIndex, usize),
-    // PtySetClipboard(PtyIndex, Box<String>),
+    /// Resets every indexed color
+    PtyResetColors(PtyIndex),
+    /// A console set the clipboard with OSC 52
+    PtySetClipboard(PtyIndex, Vec<u8>),
     PtyDectest(PtyIndex),
-    
+    PtySetTitle(PtyIndex, String),
+
     PtyReverseIndex(PtyIndex),
     PtyTerminalAttribute(PtyIndex, Attr),
     PtySetMode(PtyIndex, Mode),
     PtyUnsetMode(PtyIndex, Mode),
-    // PtySetScrollingRegion(PtyIndex, Range<Line>),
+    /// Sets the top and bottom margins, the end is past the last line of the region
+    PtySetScrollingRegion(PtyIndex, Range<Line>),
     Noop,
     Startup,
+    /// Stops the event loop, conmux is shutting down
+    Quit,
     ModeChange,
 }
 
+impl Action {
+    /// The console an action parsed from pseudoconsole output belongs to.
+    pub fn pty_index(&self) -> Option<PtyIndex> {
+        use self::Action::*;
+        match *self {
+            PtyOutput(idx, _)
+            | PtyInsertBlank(idx, _)
+            | PtyPutTabs(idx, _)
+            | PtyBackspace(idx)
+            | PtyCarriageReturn(idx)
+            | PtyLineFeed(idx)
+            | PtyNewline(idx)
+            | PtyInsertBlankLines(idx, _)
+            | PtyDeleteLines(idx, _)
+            | PtyEraseCharacters(idx, _)
+            | PtyDeleteCharacters(idx, _)
+            | PtyClearLine(idx, _)
+            | PtyClearScreen(idx, _)
+            | PtyClearTabs(idx, _)
+            | PtySubtitute(idx)
+            | PtyBell(idx)
+            | PtySetHorizontalTabstop(idx)
+            | PtyVtScrollUp(idx, _)
+            | PtyVtScrollDown(idx, _)
+            | PtyReset(idx)
+            | PtyCursorGoto(idx, _, _)
+            | PtyCursorGotoLine(idx, _)
+            | PtyCursorGotoColumn(idx, _)
+            | PtyCursorMoveUp(idx, _)
+            | PtyCursorMoveDown(idx, _)
+            | PtyCursorMoveForward(idx, _)
+            | PtyCursorMoveBackward(idx, _)
+            | PtyCursorMoveForwardTabs(idx, _)
+            | PtyCursorMoveBackwardsTabs(idx, _)
+            | PtyCursorMoveUpAndCarriageReturn(idx, _)
+            | PtyCursorMoveDownAndCarriageReturn(idx, _)
+            | PtyCursorSavePosition(idx)
+            | PtyCursorRestorePosition(idx)
+            | PtySetCursorStyle(idx)
+            | PtySetKeypadApplicationMode(idx)
+            | PtyUnsetKeypadApplicationMode(idx)
+            | PtySetActiveCharset(idx, _)
+            | PtyConfigureCharset(idx, _, _)
+            | PtySingleShift(idx, _)
+            | PtySetColor(idx, _, _)
+            | PtyResetColor(idx, _)
+            | PtyResetColors(idx)
+            | PtyDectest(idx)
+            | PtySetTitle(idx, _)
+            | PtyReverseIndex(idx)
+            | PtyTerminalAttribute(idx, _)
+            | PtySetMode(idx, _)
+            | PtyUnsetMode(idx, _)
+            | PtySetScrollingRegion(idx, _) => Some(idx),
+            _ => None,
+        }
+    }
+}
+
+pub type ConsoleFactory<'a, T> = Box<dyn FnMut(&Coord) -> Result<T> + 'a>;
 
 pub struct Context<'a, T>
 where
     T: PseudoConsole<T>,
     T: 'static,
 {
-    consoles: Vec<BufferedPseudoConsole<T>>,
-    active_console: usize,
-    _pd: PhantomData<&'a T>,
+    /// Consoles keep their index for their whole life, closed ones leave an empty slot.
+    consoles: Vec<Option<BufferedPseudoConsole<T>>>,
+    tabs: Vec<Tab>,
+    active_tab: usize,
+    dimensions: Coord,
+    dirty: bool,
+    /// Whether the host terminal has focus
+    host_focused: bool,
+    /// The console that was last told it has focus
+    focused: Option<usize>,
+    /// Lines of scrollback kept by each new console
+    scrollback_limit: usize,
+    /// Consoles whose panes are in copy mode
+    copy_modes: HashMap<usize, CopyMode>,
+    mode_keys: ModeKeys,
+    buffers: PasteBuffers,
+    /// The buffer chooser shown over the active pane
+    chooser: Option<BufferChooser>,
+    /// Name typed so far at the rename-tab prompt, while it is open
+    rename_prompt: Option<String>,
+    factory: Option<ConsoleFactory<'a, T>>,
+    tx: Sender<Action>,
 }
 
 impl<'a, T> Context<'a, T>
@@ -143,51 +259,713 @@ where
     T: PseudoConsole<T>,
     T: 'static,
 {
-    pub fn new(_: ConsoleEnabledToken) -> Context<'a, T> {
+    pub fn new(_: ConsoleEnabledToken, tx: Sender<Action>) -> Context<'a, T> {
         Context {
             consoles: Vec::new(),
-            active_console: 0,
-            _pd: PhantomData,
+            tabs: Vec::new(),
+            active_tab: 0,
+            dimensions: Coord { x: 0, y: 0 },
+            dirty: true,
+            host_focused: true,
+            focused: None,
+            scrollback_limit: DEFAULT_SCROLLBACK_LIMIT,
+            copy_modes: HashMap::new(),
+            mode_keys: ModeKeys::Emacs,
+            buffers: PasteBuffers::new(),
+            chooser: None,
+            rename_prompt: None,
+            factory: None,
+            tx,
+        }
+    }
+
+    /// Starts the shell of a console and the threads that watch its output and lifetime.
+    fn add_console(&mut self, console: T) -> Result<usize> {
+        let idx = self.consoles.len();
+        let mut console = BufferedPseudoConsole::new(console);
+        console.buffer_mut().set_scrollback_limit(self.scrollback_limit);
+        console.start_shell()?;
+        let reader = console.as_ref().reader()?;
+        let ka = console.as_ref().keep_alive();
+        self.consoles.push(Some(console));
+
+        let tx = self.tx.clone();
+        thread::spawn(move || {
+            let mut reader = reader;
+            let mut buf = [0; 4096];
+            let mut performer = OutputPerformer::new(PtyIndex(idx), tx);
+            let mut parser = Parser::new();
+            while let Ok(len) = reader.read(&mut buf) {
+                if len == 0 {
+                    break;
+                }
+                for byte in &buf[..len] {
+                    parser.advance(&mut performer, *byte);
+                }
+            }
+        });
+
+        let tx = self.tx.clone();
+        thread::spawn(move || loop {
+            if ka.dead() {
+                tx.send(Action::PtyDead(idx)).ok();
+                break;
+            }
+            thread::sleep(KEEP_ALIVE_INTERVAL);
+        });
+
+        Ok(idx)
+    }
+
+    /// Sets how many lines of scrollback consoles keep, including the ones already running.
+    pub fn set_scrollback_limit(&mut self, limit: usize) {
+        self.scrollback_limit = limit;
+        for console in self.consoles.iter_mut().flatten() {
+            console.buffer_mut().set_scrollback_limit(limit);
         }
     }
 
-    fn add_console(&mut self, console: T) -> &mut BufferedPseudoConsole<T> {
-        self.consoles.push(BufferedPseudoConsole::new(console));
-        self.consoles.last_mut().unwrap()
+    /// Scrolls the view of the active pane back into its scrollback, or forward if `lines` is negative.
+    pub fn scroll_active_view(&mut self, lines: isize) {
+        let active = self.active_console_index();
+        if let Some(console) = self.buffered_console_mut(active) {
+            console.buffer_mut().scroll_view(lines);
+            self.dirty = true;
+        }
+    }
+
+    /// Brings the active pane back to its live screen if it was scrolled back or in copy mode.
+    pub fn reset_active_view(&mut self) {
+        let active = self.active_console_index();
+        self.exit_copy_mode(active);
+        if let Some(console) = self.buffered_console_mut(active) {
+            if console.buffer_mut().reset_view() {
+                self.dirty = true;
+            }
+        }
+    }
+
+    pub fn set_mode_keys(&mut self, keys: ModeKeys) {
+        self.mode_keys = keys;
     }
 
+    /// Puts the active pane in copy mode, unless it already is.
+    pub fn enter_copy_mode(&mut self) {
+        let active = self.active_console_index();
+        if self.copy_modes.contains_key(&active) {
+            return;
+        }
+        let keys = self.mode_keys;
+        if let Some(console) = self.buffered_console_mut(active) {
+            let copy = CopyMode::new(console.buffer_mut(), keys);
+            self.copy_modes.insert(active, copy);
+            self.dirty = true;
+        }
+    }
+
+    /// Opens the search prompt of copy mode in the active pane, entering copy mode first.
+    pub fn start_search(&mut self, forward: bool) {
+        self.enter_copy_mode();
+        let active = self.active_console_index();
+        if let Some(copy) = self.copy_modes.get_mut(&active) {
+            copy.start_search(forward);
+            self.dirty = true;
+        }
+    }
+
+    pub fn exit_copy_mode(&mut self, idx: usize) {
+        if let Some(copy) = self.copy_modes.remove(&idx) {
+            if let Some(console) = self.consoles.get_mut(idx).and_then(|c| c.as_mut()) {
+                copy.exit(console.buffer_mut());
+            }
+            self.dirty = true;
+        }
+    }
+
+    pub fn copy_mode(&self, idx: usize) -> Option<&CopyMode> {
+        self.copy_modes.get(&idx)
+    }
+
+    /// Hands a key to the copy mode of the active pane, returning false if it isn't in copy mode.
+    pub fn copy_mode_key(&mut self, key: Key) -> bool {
+        let active = self.active_console_index();
+        let console = self.consoles.get_mut(active).and_then(|c| c.as_mut());
+        let action = match (self.copy_modes.get_mut(&active), console) {
+            (Some(copy), Some(console)) => copy.key(key, console.buffer_mut()),
+            _ => return false,
+        };
+
+        match action {
+            CopyAction::Continue => (),
+            CopyAction::Exit => self.exit_copy_mode(active),
+            CopyAction::Yank(text) => {
+                self.buffers.add(text.into_bytes());
+                self.exit_copy_mode(active);
+            }
+        }
+        self.dirty = true;
+        true
+    }
+
+    pub fn buffers(&self) -> &PasteBuffers {
+        &self.buffers
+    }
+
+    pub fn buffers_mut(&mut self) -> &mut PasteBuffers {
+        &mut self.buffers
+    }
+
+    /// Pastes a buffer into the active pane, the most recent one if no name is given.
+    pub fn paste_buffer(&mut self, name: Option<&str>) -> Result<()> {
+        let data = self.buffers.find(name)?.data().to_vec();
+        self.reset_active_view();
+        self.paste(&data)
+    }
+
+    /// Shows the paste buffers over the active pane to choose one to paste.
+    pub fn choose_buffer(&mut self) {
+        if !self.buffers.is_empty() {
+            self.chooser = Some(BufferChooser::new());
+            self.dirty = true;
+        }
+    }
+
+    /// Hands a key to the buffer chooser, returning false if it isn't showing.
+    pub fn chooser_key(&mut self, key: Key) -> bool {
+        let action = match self.chooser.as_mut() {
+            Some(chooser) => chooser.key(key, &mut self.buffers),
+            None => return false,
+        };
+
+        match action {
+            ChooserAction::Continue => (),
+            ChooserAction::Exit => self.chooser = None,
+            ChooserAction::Paste(name) => {
+                self.chooser = None;
+                self.paste_buffer(Some(&name)).ok();
+            }
+        }
+        self.dirty = true;
+        true
+    }
+
+    /// Creates a console of the given size with the factory set on the `EventContext`.
+    pub fn spawn_console(&mut self, size: &Coord) -> Result<usize> {
+        let console = match self.factory.as_mut() {
+            Some(factory) => factory(size)?,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    "No console factory was set",
+                ))
+            }
+        };
+        self.add_console(console)
+    }
+
+    /// Focuses a pane, switching to the tab it is in.
     pub fn set_active_console(&mut self, idx: usize) -> Result<()> {
-        if let Some(_) = self.consoles.get(idx) {
-            self.active_console = idx;
-            Ok(())
-        } else {
-            Err(Error::new(
+        match self.tabs.iter().position(|t| t.contains(idx)) {
+            Some(tab) if self.buffered_console(idx).is_some() => {
+                let was_zoomed = self.tabs[tab].zoomed();
+                self.tabs[tab].set_active_console(idx);
+                self.active_tab = tab;
+                if was_zoomed != self.tabs[tab].zoomed() {
+                    self.relayout();
+                }
+                self.dirty = true;
+                Ok(())
+            }
+            _ => Err(Error::new(
                 ErrorKind::NotFound,
                 "Console in index not found",
-            ))
+            )),
         }
     }
 
+    /// Focuses the pane on the `direction` side of the active pane, if there is one.
+    pub fn select_pane(&mut self, direction: Direction) -> Result<()> {
+        match neighbour(&self.pane_rects(), self.active_console_index(), direction) {
+            Some(idx) => self.set_active_console(idx),
+            None => Ok(()),
+        }
+    }
+
+    /// Focuses the pane after the active pane in the active tab, wrapping around.
+    pub fn next_pane(&mut self) -> Result<()> {
+        let leaves = match self.layout() {
+            Some(layout) => layout.leaves(),
+            None => return Ok(()),
+        };
+        let active = self.active_console_index();
+        let position = leaves.iter().position(|idx| *idx == active).unwrap_or(0);
+        self.set_active_console(leaves[(position + 1) % leaves.len()])
+    }
+
+    pub fn active_console_index(&self) -> usize {
+        self.active_tab().map_or(0, |t| t.active_console())
+    }
+
+    /// Removes a console from its tab and drops it, closing the tab if it was the last pane.
     pub fn delete_console(&mut self, idx: usize) {
-        self.consoles.remove(idx);
-        // handle idx 0
-        // handle active_console -1
+        self.copy_modes.remove(&idx);
+        if let Some(slot) = self.consoles.get_mut(idx) {
+            *slot = None;
+        }
+
+        if let Some(tab) = self.tabs.iter().position(|t| t.contains(idx)) {
+            if !self.tabs[tab].close(idx) {
+                self.remove_tab(tab);
+            }
+        }
+        self.relayout();
+    }
+
+    /// Asks the event loop to stop once the current action has been handled.
+    pub fn quit(&self) {
+        self.tx.send(Action::Quit).ok();
+    }
+
+    /// Closes every tab, dropping their consoles, and stops the event loop.
+    pub fn kill_server(&mut self) {
+        while !self.tabs.is_empty() {
+            self.close_tab(0);
+        }
+        self.quit();
+    }
+
+    /// Whether every console has been closed.
+    pub fn is_empty(&self) -> bool {
+        self.consoles.iter().all(|c| c.is_none())
+    }
+
+    pub fn tabs(&self) -> &[Tab] {
+        &self.tabs
+    }
+
+    pub fn active_tab_index(&self) -> usize {
+        self.active_tab
+    }
+
+    pub fn active_tab(&self) -> Option<&Tab> {
+        self.tabs.get(self.active_tab)
+    }
+
+    fn active_tab_mut(&mut self) -> Option<&mut Tab> {
+        self.tabs.get_mut(self.active_tab)
+    }
+
+    /// Opens a tab with a new console and switches to it.
+    pub fn new_tab(&mut self) -> Result<usize> {
+        let size = self.pane_area().size;
+        let idx = self.spawn_console(&size)?;
+        self.tabs.push(Tab::new(idx));
+        self.active_tab = self.tabs.len() - 1;
+        self.relayout();
+        Ok(self.active_tab)
     }
+
+    /// Closes a tab along with every console in it.
+    pub fn close_tab(&mut self, tab: usize) {
+        if tab >= self.tabs.len() {
+            return;
+        }
+        for idx in self.tabs[tab].layout().leaves() {
+            if let Some(slot) = self.consoles.get_mut(idx) {
+                *slot = None;
+            }
+        }
+        self.remove_tab(tab);
+        self.relayout();
+    }
+
+    fn remove_tab(&mut self, tab: usize) {
+        self.tabs.remove(tab);
+        if self.active_tab > tab || self.active_tab >= self.tabs.len() {
+            self.active_tab = self.active_tab.saturating_sub(1);
+        }
+    }
+
+    pub fn select_tab(&mut self, tab: usize) -> Result<()> {
+        if tab < self.tabs.len() {
+            self.active_tab = tab;
+            self.dirty = true;
+            Ok(())
+        } else {
+            Err(Error::new(ErrorKind::NotFound, "Tab in index not found"))
+        }
+    }
+
+    pub fn next_tab(&mut self) {
+        if !self.tabs.is_empty() {
+            self.active_tab = (self.active_tab + 1) % self.tabs.len();
+            self.dirty = true;
+        }
+    }
+
+    pub fn previous_tab(&mut self) {
+        if !self.tabs.is_empty() {
+            self.active_tab = (self.active_tab + self.tabs.len() - 1) % self.tabs.len();
+            self.dirty = true;
+        }
+    }
+
+    pub fn rename_tab(&mut self, tab: usize, name: impl Into<String>) {
+        if let Some(tab) = self.tabs.get_mut(tab) {
+            tab.rename(name);
+            self.dirty = true;
+        }
+    }
+
+    /// The name of a tab, falling back to the title of its active pane.
+    /// Opens a prompt in the status line to type a new name for the active tab.
+    pub fn start_rename_tab(&mut self) {
+        if !self.tabs.is_empty() {
+            self.rename_prompt = Some(String::new());
+            self.dirty = true;
+        }
+    }
+
+    /// Edits the rename-tab prompt, returning false if it isn't open.
+    ///
+    /// Enter renames the active tab, an empty name going back to the automatic one.
+    pub fn rename_prompt_key(&mut self, key: Key) -> bool {
+        let name = match self.rename_prompt.as_mut() {
+            Some(name) => name,
+            None => return false,
+        };
+
+        match (key.code, key.modifiers.ctrl) {
+            (KeyCode::Enter, _) => {
+                let name = self.rename_prompt.take().unwrap_or_default();
+                self.rename_tab(self.active_tab, name);
+            }
+            (KeyCode::Escape, _) | (KeyCode::Char('g'), true) | (KeyCode::Char('c'), true) => {
+                self.rename_prompt = None
+            }
+            (KeyCode::Backspace, _) => {
+                name.pop();
+            }
+            (KeyCode::Char('u'), true) => name.clear(),
+            (KeyCode::Char(c), false) if !key.modifiers.alt => name.push(c),
+            _ => (),
+        }
+        self.dirty = true;
+        true
+    }
+
+    pub fn tab_name(&self, tab: usize) -> String {
+        let tab = match self.tabs.get(tab) {
+            Some(tab) => tab,
+            None => return String::new(),
+        };
+
+        tab.name()
+            .map(|n| n.to_owned())
+            .or_else(|| {
+                self.buffered_console(tab.active_console())
+                    .and_then(|c| c.buffer().title().map(|t| t.to_owned()))
+            })
+            .unwrap_or_else(|| String::from("shell"))
+    }
+
+    pub fn layout(&self) -> Option<&Layout> {
+        self.active_tab().map(|t| t.layout())
+    }
+
+    /// Size of the host surface the panes are laid out on.
+    pub fn dimensions(&self) -> &Coord {
+        &self.dimensions
+    }
+
+    /// Resizes the host surface, resizing every console to its new pane.
+    pub fn resize(&mut self, dimensions: Coord) {
+        self.dimensions = dimensions;
+        self.relayout();
+    }
+
+    /// Region of the surface panes are laid out in, everything but the status line.
+    pub fn pane_area(&self) -> Rect {
+        Rect::new(
+            Coord { x: 0, y: 0 },
+            Coord {
+                x: self.dimensions.x,
+                y: self.dimensions.y.saturating_sub(1),
+            },
+        )
+    }
+
+    /// The rectangles of the panes of the active tab.
+    pub fn pane_rects(&self) -> Vec<(usize, Rect)> {
+        self.active_tab()
+            .map(|t| t.rects(self.pane_area()))
+            .unwrap_or_default()
+    }
+
+    /// Whether the active pane is temporarily taking up the whole surface.
+    pub fn zoomed(&self) -> bool {
+        self.active_tab().map_or(false, |t| t.zoomed())
+    }
+
+    /// Gives the active pane the whole surface, or puts it back in the layout.
+    ///
+    /// Panes hidden by the zoom keep their size, so only the active pane is resized either way.
+    pub fn toggle_zoom(&mut self) {
+        if let Some(tab) = self.active_tab_mut() {
+            tab.toggle_zoom();
+        }
+        self.relayout();
+    }
+
+    /// Text shown in the status line at the bottom of the surface.
+    pub fn status(&self) -> String {
+        let tabs: Vec<String> = (0..self.tabs.len())
+            .map(|i| {
+                let mut tab = format!("{}:{}", i, self.tab_name(i));
+                if i == self.active_tab {
+                    tab.push('*');
+                    if self.zoomed() {
+                        tab.push('Z');
+                    }
+                }
+                tab
+            })
+            .collect();
+        let mut status = format!("[conmux] {}", tabs.join(" "));
+        if let Some(name) = self.rename_prompt.as_ref() {
+            status.push_str(&format!(" (rename-tab) {}", name));
+            return status;
+        }
+
+        let active = self.active_console_index();
+        if let Some(copy) = self.copy_modes.get(&active) {
+            status.push(' ');
+            status.push_str(&copy.status());
+        }
+        if let Some(console) = self.buffered_console(active) {
+            let buffer = console.buffer();
+            if buffer.view_offset() > 0 {
+                status.push_str(&format!(" [{}/{}]", buffer.view_offset(), buffer.scrollback().len()));
+            }
+        }
+        status
+    }
+
+    /// Resizes each console whose pane no longer matches its size, in every tab.
+    pub fn relayout(&mut self) {
+        let area = self.pane_area();
+        let rects: Vec<(usize, Rect)> = self.tabs.iter().flat_map(|t| t.rects(area)).collect();
+        for (i, rect) in rects {
+            if let Some(Some(console)) = self.consoles.get_mut(i) {
+                if *console.dimensions() != rect.size {
+                    console.resize(&rect.size).ok();
+                }
+            }
+        }
+        self.dirty = true;
+    }
+
+    /// Splits the active pane, starting a new console in the new half and focusing it.
+    ///
+    /// Opens the first tab if there are none yet.
+    pub fn split(&mut self, orientation: Orientation) -> Result<usize> {
+        if self.tabs.is_empty() {
+            self.new_tab()?;
+            return Ok(self.active_console_index());
+        }
+
+        if self.zoomed() {
+            self.toggle_zoom();
+        }
+
+        let active = self.active_console_index();
+        let size = match self.pane_rects().into_iter().find(|(i, _)| *i == active) {
+            Some((_, rect)) => {
+                let (first, second) = split_rect(rect, orientation, 0.5);
+                if [first.size, second.size].iter().any(|s| s.x == 0 || s.y == 0) {
+                    return Err(Error::new(ErrorKind::InvalidInput, "Pane too small to split"));
+                }
+                second.size
+            }
+            None => self.pane_area().size,
+        };
+
+        let idx = self.spawn_console(&size)?;
+        if let Some(tab) = self.active_tab_mut() {
+            tab.split(idx, orientation);
+        }
+        self.relayout();
+        Ok(idx)
+    }
+
+    /// Moves the border of a split of the active tab, without resizing the consoles yet.
+    ///
+    /// Panes are drawn at their new size right away, `relayout` resizes the consoles to match.
+    pub fn set_split_ratio(&mut self, path: &[bool], ratio: f32) {
+        let area = self.pane_area();
+        if let Some(tab) = self.active_tab_mut() {
+            tab.layout_mut().set_ratio(area, path, ratio);
+            self.dirty = true;
+        }
+    }
+
+    pub fn swap_panes(&mut self, a: usize, b: usize) {
+        if let Some(tab) = self.active_tab_mut() {
+            tab.layout_mut().swap(a, b);
+        }
+        self.relayout();
+    }
+
+    pub fn rotate_panes(&mut self) {
+        if let Some(tab) = self.active_tab_mut() {
+            tab.layout_mut().rotate();
+        }
+        self.relayout();
+    }
+
+    pub fn rebalance_panes(&mut self) {
+        if let Some(tab) = self.active_tab_mut() {
+            tab.layout_mut().rebalance();
+        }
+        self.relayout();
+    }
+
+    /// The buffer and position of every pane on the surface.
+    pub fn panes(&self) -> Vec<Pane> {
+        let active = self.active_console_index();
+        self.pane_rects()
+            .into_iter()
+            .filter_map(|(i, rect)| {
+                self.buffered_console(i).map(|c| Pane {
+                    buffer: c.buffer(),
+                    rect,
+                    active: i == active,
+                    copy: self.copy_modes.get(&i),
+                    menu: match self.chooser.as_ref() {
+                        Some(chooser) if i == active => Some(chooser.menu(&self.buffers, rect.size.x)),
+                        _ => None,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    pub fn set_host_focused(&mut self, focused: bool) {
+        self.host_focused = focused;
+    }
+
+    /// Tells consoles that asked for focus reports when they gain or lose focus.
+    ///
+    /// A console has focus while it is the active pane and the host terminal has focus.
+    pub fn sync_focus(&mut self) {
+        let focused = if self.host_focused && !self.tabs.is_empty() {
+            Some(self.active_console_index())
+        } else {
+            None
+        };
+
+        if focused == self.focused {
+            return;
+        }
+        if let Some(idx) = self.focused {
+            self.report_focus(idx, false);
+        }
+        if let Some(idx) = focused {
+            self.report_focus(idx, true);
+        }
+        self.focused = focused;
+    }
+
+    fn report_focus(&mut self, idx: usize, focused: bool) {
+        let reports = self
+            .buffered_console(idx)
+            .map_or(false, |c| c.buffer().mode(Mode::ReportFocusInOut));
+        if !reports {
+            return;
+        }
+
+        if let Some(console) = self.console_mut(idx) {
+            let writer = console.writer();
+            writer
+                .write_all(if focused { b"\x1b[I" } else { b"\x1b[O" })
+                .and_then(|_| writer.flush())
+                .ok();
+        }
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Returns whether anything changed since the last call.
+    pub fn take_dirty(&mut self) -> bool {
+        let dirty = self.dirty;
+        self.dirty = false;
+        dirty
+    }
+
+    pub fn buffered_console(&self, i: usize) -> Option<&BufferedPseudoConsole<T>> {
+        self.consoles.get(i).and_then(|c| c.as_ref())
+    }
+
+    pub fn buffered_console_mut(&mut self, i: usize) -> Option<&mut BufferedPseudoConsole<T>> {
+        self.consoles.get_mut(i).and_then(|c| c.as_mut())
+    }
+
     pub fn console(&self, i: usize) -> Option<&T> {
-        self.consoles.get(i).and_then(|c| Some(c.as_ref()))
+        self.buffered_console(i).and_then(|c| Some(c.as_ref()))
     }
 
     pub fn console_mut(&mut self, i: usize) -> Option<&mut T> {
-        self.consoles.get_mut(i).and_then(|c| Some(c.as_mut()))
+        self.buffered_console_mut(i).and_then(|c| Some(c.as_mut()))
     }
 
-    pub fn active_console_mut(&mut self) -> &mut T {
-        self.consoles[self.active_console].as_mut()
+    pub fn active_console_mut(&mut self) -> Option<&mut T> {
+        let active = self.active_console_index();
+        self.console_mut(active)
     }
 
-    pub fn active_console(&self) -> &T {
-        self.consoles[self.active_console].as_ref()
+    pub fn active_console(&self) -> Option<&T> {
+        self.console(self.active_console_index())
     }
+
+    /// Pastes text into the active console in one write.
+    ///
+    /// The text is wrapped in paste brackets if the console turned on bracketed paste, so
+    /// shells don't run a multi-line paste line by line.
+    pub fn paste(&mut self, text: &[u8]) -> Result<()> {
+        let active = self.active_console_index();
+        let bracketed = match self.buffered_console(active) {
+            Some(console) => console.buffer().mode(Mode::BracketedPaste),
+            None => return Ok(()),
+        };
+
+        let mut bytes = Vec::with_capacity(text.len() + 12);
+        if bracketed {
+            bytes.extend_from_slice(PASTE_START);
+            // A paste can't be allowed to end the brackets early and smuggle in keys.
+            let mut rest = text;
+            while let Some(end) = find(rest, PASTE_END) {
+                bytes.extend_from_slice(&rest[..end]);
+                rest = &rest[end + PASTE_END.len()..];
+            }
+            bytes.extend_from_slice(rest);
+            bytes.extend_from_slice(PASTE_END);
+        } else {
+            bytes.extend_from_slice(text);
+        }
+
+        let writer = match self.active_console_mut() {
+            Some(console) => console.writer(),
+            None => return Ok(()),
+        };
+        writer.write_all(&bytes)?;
+        writer.flush()
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
 }
 
 pub struct EventContext<'a, T>
@@ -196,7 +974,7 @@ where
     T: 'static,
 {
     receivers: Vec<Receiver<Action>>,
-    handlers: Vec<Box<FnMut(&mut Context<T>, Action) -> Option<()> + 'a>>,
+    handlers: Vec<Box<dyn FnMut(&mut Context<T>, Action) -> Option<()> + 'a>>,
     context: Context<'a, T>,
 }
 
@@ -207,11 +985,35 @@ where
     T: PseudoConsole<T>,
 {
     pub fn new(t: ConsoleEnabledToken) -> EventContext<'a, T> {
-        EventContext {
-            receivers: Vec::new(),
+        // Console output comes in through a channel shared by every console.
+        let (tx, rx) = unbounded();
+        let mut ectx: EventContext<T> = EventContext {
+            receivers: vec![rx],
             handlers: Vec::new(),
-            context: Context::new(t),
-        }
+            context: Context::new(t, tx),
+        };
+
+        ectx.handler(|ctx, action| {
+            match action {
+                Action::HostResize(dimensions) => ctx.resize(dimensions),
+                Action::HostFocus(focused) => ctx.set_host_focused(focused),
+                Action::PtySetClipboard(_, data) => {
+                    ctx.buffers_mut().add(data);
+                }
+                Action::PtyResize(idx, c) => {
+                    ctx.buffered_console_mut(idx)?.resize(&c).unwrap();
+                }
+                action => {
+                    if let Some(PtyIndex(idx)) = action.pty_index() {
+                        ctx.buffered_console_mut(idx)?.buffer_mut().perform(&action);
+                        ctx.mark_dirty();
+                    }
+                }
+            }
+            None
+        });
+
+        ectx
     }
 
     pub fn handler<F>(&mut self, f: F)
@@ -222,36 +1024,23 @@ where
         self.handlers.push(Box::new(f));
     }
 
-    pub fn add_console(&mut self, console: T) {
-        let console = self.context.add_console(console);
-        console.start_shell().unwrap();
-        let reader = console.as_ref().reader().clone();
-        let ka = console.as_ref().keep_alive();
-
-        self.sender(|tx| {
-            let mut rx = reader.bytes();
-            while let Some(Ok(c)) = rx.next() {
-                tx.send(Action::PtyOutReceived(0, c)).unwrap();
-            }
-            Ok(())
-        });
-
-        self.sender(move |tx| {
-            loop {
-                if ka.dead() {
-                    tx.send(Action::PtyDead(0)).unwrap();
-                    break;
-                }
-            }
-            Ok(())
-        });
+    /// Sets how new consoles are created when panes are split.
+    pub fn console_factory<F>(&mut self, f: F)
+    where
+        F: FnMut(&Coord) -> Result<T>,
+        F: 'a,
+    {
+        self.context.factory = Some(Box::new(f));
+    }
 
-        self.handler(|ctx, action| {
-            if let Action::PtyResize(0, c) = action {
-                ctx.console_mut(0)?.resize(&c).unwrap();
-            }
-            None
-        });
+    /// Adds a console as a new pane, splitting the active pane if there already is one.
+    pub fn add_console(&mut self, console: T) {
+        let idx = self.context.add_console(console).unwrap();
+        match self.context.active_tab_mut() {
+            Some(tab) => tab.split(idx, Orientation::Horizontal),
+            None => self.context.tabs.push(Tab::new(idx)),
+        }
+        self.context.relayout();
     }
 
     pub fn sender<F>(&mut self, f: F) -> (JoinHandle<Result<()>>, QuitSignal)
@@ -266,35 +1055,56 @@ where
         (thread::spawn(move || f(tx.clone())), qtx)
     }
 
-    fn next(&self) -> Option<Action> {
-        if self.receivers.len() == 0 {
-            return None;
-        }
-        let mut select = Select::new();
-        let mut select_col = Vec::new();
-        for recv in self.receivers.iter() {
-            select_col.push((select.recv(recv), recv));
-        }
-        let oper = select.select();
-        let index = oper.index();
-        // todo: no need to make this n d we?
-        // binary search?
-        for (i, r) in select_col {
-            if i == index {
-                return oper.recv(r).ok();
+    fn next(&mut self) -> Option<Action> {
+        loop {
+            if self.receivers.is_empty() {
+                return None;
             }
+            let index = {
+                let mut select = Select::new();
+                for recv in self.receivers.iter() {
+                    select.recv(recv);
+                }
+                let oper = select.select();
+                let index = oper.index();
+                match oper.recv(&self.receivers[index]) {
+                    Ok(action) => return Some(action),
+                    Err(_) => index,
+                }
+            };
+            // The sender hung up, stop selecting on it so it doesn't spin the loop.
+            self.receivers.remove(index);
+        }
+    }
+
+    /// Waits for the next action and runs every handler on it.
+    ///
+    /// Returns the action that was handled, or `None` if there are no senders.
+    pub fn step(&mut self) -> Option<Action> {
+        let action = self.next()?;
+        for f in self.handlers.iter_mut() {
+            f(&mut self.context, action.clone());
         }
-        None
+        // Any handler could have changed the active pane.
+        self.context.sync_focus();
+        Some(action)
+    }
+
+    pub fn context(&self) -> &Context<'a, T> {
+        &self.context
     }
 
+    pub fn context_mut(&mut self) -> &mut Context<'a, T> {
+        &mut self.context
+    }
+
+    /// Handles actions until one of them is `Quit`, or every sender has hung up.
     pub fn start_event_loop(&mut self) {
         self.sender(|tx| Ok(tx.send(Action::Startup).unwrap()));
 
-        loop {
-            if let Some(action) = self.next() {
-                for f in self.handlers.iter_mut() {
-                    f(&mut self.context, action);
-                }
+        while let Some(action) = self.step() {
+            if let Action::Quit = action {
+                break;
             }
         }
     }
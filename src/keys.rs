@@ -0,0 +1,422 @@
+use crate::ansitypes::*;
+
+use std::fmt::Write;
+
+/// A key on the host keyboard, without any modifiers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum KeyCode {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Left,
+    Right,
+    /// Function keys F1 to F12
+    F(u8),
+}
+
+/// Modifier keys held while a key was pressed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+}
+
+impl Modifiers {
+    /// Decodes the modifier parameter of an xterm key sequence, `1 + shift | alt << 1 | ctrl << 2`.
+    pub fn from_param(param: i64) -> Modifiers {
+        let bits = (param - 1).max(0);
+        Modifiers {
+            shift: bits & 1 != 0,
+            alt: bits & 2 != 0,
+            ctrl: bits & 4 != 0,
+        }
+    }
+
+    /// The modifier parameter of an xterm key sequence, `None` if no modifiers are held.
+    pub fn param(&self) -> Option<u8> {
+        let bits = self.shift as u8 | (self.alt as u8) << 1 | (self.ctrl as u8) << 2;
+        if bits == 0 {
+            None
+        } else {
+            Some(bits + 1)
+        }
+    }
+}
+
+/// A key press on the host, as far as it can be told apart from the input stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub code: KeyCode,
+    pub modifiers: Modifiers,
+}
+
+impl Key {
+    pub fn new(code: KeyCode) -> Key {
+        Key {
+            code,
+            modifiers: Modifiers::default(),
+        }
+    }
+
+    pub fn with_modifiers(code: KeyCode, modifiers: Modifiers) -> Key {
+        Key { code, modifiers }
+    }
+
+    pub fn ctrl(c: char) -> Key {
+        Key {
+            code: KeyCode::Char(c),
+            modifiers: Modifiers {
+                ctrl: true,
+                ..Modifiers::default()
+            },
+        }
+    }
+
+    /// The key for a control byte, control letters are typed with Control held.
+    pub fn from_control(byte: u8) -> Option<Key> {
+        let key = match byte {
+            C0::NUL => Key::ctrl(' '),
+            C0::HT => Key::new(KeyCode::Tab),
+            C0::CR => Key::new(KeyCode::Enter),
+            C0::ESC => Key::new(KeyCode::Escape),
+            0x01..=0x1a => Key::ctrl((b'a' + byte - 1) as char),
+            0x1c..=0x1f => Key::ctrl((b'\\' + byte - 0x1c) as char),
+            C0::DEL => Key::new(KeyCode::Backspace),
+            _ => return None,
+        };
+        Some(key)
+    }
+
+    /// The key for the number in a `CSI n ~` sequence.
+    pub fn from_tilde(number: i64, modifiers: Modifiers) -> Option<Key> {
+        let code = match number {
+            1 | 7 => KeyCode::Home,
+            2 => KeyCode::Insert,
+            3 => KeyCode::Delete,
+            4 | 8 => KeyCode::End,
+            5 => KeyCode::PageUp,
+            6 => KeyCode::PageDown,
+            11..=15 => KeyCode::F((number - 10) as u8),
+            17..=21 => KeyCode::F((number - 11) as u8),
+            23 | 24 => KeyCode::F((number - 12) as u8),
+            _ => return None,
+        };
+        Some(Key::with_modifiers(code, modifiers))
+    }
+
+    /// The key for the final character of a `CSI` or `SS3` key sequence.
+    pub fn from_final(c: char, modifiers: Modifiers) -> Option<Key> {
+        let code = match c {
+            'A' => KeyCode::Up,
+            'B' => KeyCode::Down,
+            'C' => KeyCode::Right,
+            'D' => KeyCode::Left,
+            'H' => KeyCode::Home,
+            'F' => KeyCode::End,
+            'P' => KeyCode::F(1),
+            'Q' => KeyCode::F(2),
+            'R' => KeyCode::F(3),
+            'S' => KeyCode::F(4),
+            // Back tab, sent for Shift-Tab
+            'Z' => {
+                return Some(Key::with_modifiers(
+                    KeyCode::Tab,
+                    Modifiers {
+                        shift: true,
+                        ..modifiers
+                    },
+                ))
+            }
+            _ => return None,
+        };
+        Some(Key::with_modifiers(code, modifiers))
+    }
+
+    /// Parses a key chord like `C-b`, `M-Left` or `%`.
+    ///
+    /// Modifiers are `C-` for Control, `M-` for Alt and `S-` for Shift, in any order.
+    /// Shift on a character is only allowed for letters without Control, giving the capital.
+    pub fn parse(chord: &str) -> Option<Key> {
+        let mut modifiers = Modifiers::default();
+        let mut rest = chord;
+        while rest.len() > 2 && rest.as_bytes()[1] == b'-' {
+            match rest.as_bytes()[0] {
+                b'C' => modifiers.ctrl = true,
+                b'M' => modifiers.alt = true,
+                b'S' => modifiers.shift = true,
+                _ => return None,
+            }
+            rest = &rest[2..];
+        }
+
+        let code = match rest {
+            "Enter" => KeyCode::Enter,
+            "Tab" => KeyCode::Tab,
+            "BTab" => {
+                modifiers.shift = true;
+                KeyCode::Tab
+            }
+            "BSpace" => KeyCode::Backspace,
+            "Escape" => KeyCode::Escape,
+            "IC" | "Insert" => KeyCode::Insert,
+            "DC" | "Delete" => KeyCode::Delete,
+            "Home" => KeyCode::Home,
+            "End" => KeyCode::End,
+            "PPage" | "PageUp" => KeyCode::PageUp,
+            "NPage" | "PageDown" => KeyCode::PageDown,
+            "Up" => KeyCode::Up,
+            "Down" => KeyCode::Down,
+            "Left" => KeyCode::Left,
+            "Right" => KeyCode::Right,
+            "Space" => KeyCode::Char(' '),
+            f if f.len() > 1 && f.starts_with('F') => match f[1..].parse() {
+                Ok(n @ 1..=12) => KeyCode::F(n),
+                _ => return None,
+            },
+            _ => {
+                let mut chars = rest.chars();
+                match (chars.next(), chars.next()) {
+                    // Shifted characters come in as the character itself, `S-a` is `A`.
+                    (Some(c), None) if modifiers.shift => {
+                        if modifiers.ctrl || !c.is_ascii_alphabetic() {
+                            return None;
+                        }
+                        modifiers.shift = false;
+                        KeyCode::Char(c.to_ascii_uppercase())
+                    }
+                    // Control chords come in as lowercase letters.
+                    (Some(c), None) if modifiers.ctrl => KeyCode::Char(c.to_ascii_lowercase()),
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => return None,
+                }
+            }
+        };
+
+        Some(Key { code, modifiers })
+    }
+
+    /// The bytes a terminal sends for this key.
+    ///
+    /// `cursor_keys` is whether the receiving console turned on application cursor keys,
+    /// which sends unmodified arrows, Home and End as `SS3` rather than `CSI` sequences.
+    pub fn encode(&self, cursor_keys: bool) -> Vec<u8> {
+        let modifiers = self.modifiers.param();
+        let mut out = String::new();
+
+        // Keys sent as a single character carry Alt as a leading escape instead.
+        let simple = |bytes: &[u8]| {
+            let mut encoded = Vec::with_capacity(bytes.len() + 1);
+            if self.modifiers.alt {
+                encoded.push(C0::ESC);
+            }
+            encoded.extend_from_slice(bytes);
+            encoded
+        };
+
+        let cursor = |c: char, out: &mut String| match modifiers {
+            Some(m) => write!(out, "\x1b[1;{}{}", m, c).unwrap(),
+            None if cursor_keys => write!(out, "\x1bO{}", c).unwrap(),
+            None => write!(out, "\x1b[{}", c).unwrap(),
+        };
+
+        let tilde = |n: u8, out: &mut String| match modifiers {
+            Some(m) => write!(out, "\x1b[{};{}~", n, m).unwrap(),
+            None => write!(out, "\x1b[{}~", n).unwrap(),
+        };
+
+        match self.code {
+            KeyCode::Char(c) if self.modifiers.ctrl => {
+                return simple(&[match c {
+                    ' ' | '@' | '2' => C0::NUL,
+                    '?' | '8' => C0::DEL,
+                    c => (c.to_ascii_uppercase() as u8) & 0x1f,
+                }])
+            }
+            KeyCode::Char(c) => {
+                let mut buf = [0; 4];
+                return simple(c.encode_utf8(&mut buf).as_bytes());
+            }
+            KeyCode::Enter => return simple(&[C0::CR]),
+            KeyCode::Tab if self.modifiers.shift => out.push_str("\x1b[Z"),
+            KeyCode::Tab => return simple(&[C0::HT]),
+            KeyCode::Backspace if self.modifiers.ctrl => return simple(&[C0::BS]),
+            KeyCode::Backspace => return simple(&[C0::DEL]),
+            KeyCode::Escape => return simple(&[C0::ESC]),
+            KeyCode::Up => cursor('A', &mut out),
+            KeyCode::Down => cursor('B', &mut out),
+            KeyCode::Right => cursor('C', &mut out),
+            KeyCode::Left => cursor('D', &mut out),
+            KeyCode::Home => cursor('H', &mut out),
+            KeyCode::End => cursor('F', &mut out),
+            KeyCode::Insert => tilde(2, &mut out),
+            KeyCode::Delete => tilde(3, &mut out),
+            KeyCode::PageUp => tilde(5, &mut out),
+            KeyCode::PageDown => tilde(6, &mut out),
+            KeyCode::F(n @ 1..=4) => {
+                let c = (b'P' + n - 1) as char;
+                match modifiers {
+                    Some(m) => write!(out, "\x1b[1;{}{}", m, c).unwrap(),
+                    None => write!(out, "\x1bO{}", c).unwrap(),
+                }
+            }
+            KeyCode::F(n @ 5) => tilde(n + 10, &mut out),
+            KeyCode::F(n @ 6..=10) => tilde(n + 11, &mut out),
+            KeyCode::F(n @ 11..=12) => tilde(n + 12, &mut out),
+            KeyCode::F(_) => (),
+        }
+        out.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modified(code: KeyCode, ctrl: bool, alt: bool, shift: bool) -> Key {
+        Key::with_modifiers(code, Modifiers { ctrl, alt, shift })
+    }
+
+    /// Checks `key` encodes to `expected` whether or not application cursor keys are on.
+    fn assert_encodes(key: Key, expected: &[u8]) {
+        assert_eq!(key.encode(false), expected, "{:?}", key);
+        assert_eq!(key.encode(true), expected, "{:?} with cursor keys", key);
+    }
+
+    #[test]
+    fn cursor_keys_switch_to_ss3() {
+        let keys = [
+            (KeyCode::Up, 'A'),
+            (KeyCode::Down, 'B'),
+            (KeyCode::Right, 'C'),
+            (KeyCode::Left, 'D'),
+            (KeyCode::Home, 'H'),
+            (KeyCode::End, 'F'),
+        ];
+        for (code, c) in keys.iter() {
+            let key = Key::new(*code);
+            assert_eq!(key.encode(false), format!("\x1b[{}", c).as_bytes());
+            assert_eq!(key.encode(true), format!("\x1bO{}", c).as_bytes());
+        }
+    }
+
+    #[test]
+    fn modified_cursor_keys_are_always_csi() {
+        assert_encodes(modified(KeyCode::Up, true, false, false), b"\x1b[1;5A");
+        assert_encodes(modified(KeyCode::Left, false, false, true), b"\x1b[1;2D");
+        assert_encodes(modified(KeyCode::Right, false, true, false), b"\x1b[1;3C");
+        assert_encodes(modified(KeyCode::Home, true, true, false), b"\x1b[1;7H");
+        assert_encodes(modified(KeyCode::End, true, true, true), b"\x1b[1;8F");
+    }
+
+    #[test]
+    fn function_keys() {
+        let expected: [&[u8]; 12] = [
+            b"\x1bOP", b"\x1bOQ", b"\x1bOR", b"\x1bOS", b"\x1b[15~", b"\x1b[17~",
+            b"\x1b[18~", b"\x1b[19~", b"\x1b[20~", b"\x1b[21~", b"\x1b[23~", b"\x1b[24~",
+        ];
+        for (n, bytes) in (1..=12).zip(expected.iter()) {
+            assert_encodes(Key::new(KeyCode::F(n)), bytes);
+        }
+
+        assert_encodes(modified(KeyCode::F(1), false, false, true), b"\x1b[1;2P");
+        assert_encodes(modified(KeyCode::F(5), true, false, false), b"\x1b[15;5~");
+        assert_encodes(modified(KeyCode::F(12), false, true, false), b"\x1b[24;3~");
+    }
+
+    #[test]
+    fn editing_keys() {
+        assert_encodes(Key::new(KeyCode::Insert), b"\x1b[2~");
+        assert_encodes(Key::new(KeyCode::Delete), b"\x1b[3~");
+        assert_encodes(Key::new(KeyCode::PageUp), b"\x1b[5~");
+        assert_encodes(Key::new(KeyCode::PageDown), b"\x1b[6~");
+        assert_encodes(modified(KeyCode::PageUp, false, true, false), b"\x1b[5;3~");
+        assert_encodes(modified(KeyCode::Insert, true, false, true), b"\x1b[2;6~");
+    }
+
+    #[test]
+    fn characters_and_control_keys() {
+        assert_encodes(Key::new(KeyCode::Char('é')), "é".as_bytes());
+        assert_encodes(Key::ctrl('b'), b"\x02");
+        assert_encodes(Key::ctrl(' '), b"\x00");
+        assert_encodes(modified(KeyCode::Char('x'), false, true, false), b"\x1bx");
+        assert_encodes(Key::new(KeyCode::Enter), b"\r");
+        assert_encodes(modified(KeyCode::Tab, false, false, true), b"\x1b[Z");
+        assert_encodes(Key::new(KeyCode::Backspace), b"\x7f");
+        assert_encodes(modified(KeyCode::Backspace, true, false, false), b"\x08");
+    }
+
+    #[test]
+    fn tilde_numbers_decode_to_what_is_encoded() {
+        let keys = [
+            KeyCode::Insert,
+            KeyCode::Delete,
+            KeyCode::PageUp,
+            KeyCode::PageDown,
+            KeyCode::F(5),
+            KeyCode::F(8),
+            KeyCode::F(10),
+            KeyCode::F(11),
+            KeyCode::F(12),
+        ];
+        let modifiers = Modifiers::from_param(5);
+        for code in keys.iter() {
+            let key = Key::with_modifiers(*code, modifiers);
+            let encoded = String::from_utf8(key.encode(false)).unwrap();
+            let number = encoded[2..encoded.find(';').unwrap()].parse().unwrap();
+            assert_eq!(Key::from_tilde(number, modifiers), Some(key));
+        }
+
+        let none = Modifiers::default();
+        assert_eq!(Key::from_tilde(1, none), Some(Key::new(KeyCode::Home)));
+        assert_eq!(Key::from_tilde(7, none), Some(Key::new(KeyCode::Home)));
+        assert_eq!(Key::from_tilde(4, none), Some(Key::new(KeyCode::End)));
+        assert_eq!(Key::from_tilde(8, none), Some(Key::new(KeyCode::End)));
+        assert_eq!(Key::from_tilde(16, none), None);
+        assert_eq!(Key::from_tilde(22, none), None);
+        assert_eq!(Key::from_tilde(25, none), None);
+    }
+
+    #[test]
+    fn parse_chords() {
+        assert_eq!(Key::parse("C-b"), Some(Key::ctrl('b')));
+        assert_eq!(Key::parse("C-B"), Some(Key::ctrl('b')));
+        assert_eq!(Key::parse("%"), Some(Key::new(KeyCode::Char('%'))));
+        assert_eq!(Key::parse("-"), Some(Key::new(KeyCode::Char('-'))));
+        assert_eq!(Key::parse("M-Left"), Some(modified(KeyCode::Left, false, true, false)));
+        assert_eq!(Key::parse("S-Up"), Some(modified(KeyCode::Up, false, false, true)));
+        assert_eq!(Key::parse("M-C-x"), Some(modified(KeyCode::Char('x'), true, true, false)));
+        assert_eq!(Key::parse("BTab"), Some(modified(KeyCode::Tab, false, false, true)));
+        assert_eq!(Key::parse("Space"), Some(Key::new(KeyCode::Char(' '))));
+        assert_eq!(Key::parse("F12"), Some(Key::new(KeyCode::F(12))));
+        assert_eq!(Key::parse("NPage"), Some(Key::new(KeyCode::PageDown)));
+
+        assert_eq!(Key::parse("F13"), None);
+        assert_eq!(Key::parse("F0"), None);
+        assert_eq!(Key::parse("X-a"), None);
+        assert_eq!(Key::parse("ab"), None);
+        assert_eq!(Key::parse(""), None);
+    }
+
+    #[test]
+    fn shifted_letters_parse_as_capitals() {
+        assert_eq!(Key::parse("S-a"), Some(Key::new(KeyCode::Char('A'))));
+        assert_eq!(
+            Key::parse("M-S-z"),
+            Some(modified(KeyCode::Char('Z'), false, true, false))
+        );
+        // The host can't report these, so they could never be pressed.
+        assert_eq!(Key::parse("S-1"), None);
+        assert_eq!(Key::parse("C-S-a"), None);
+    }
+}
@@ -0,0 +1,560 @@
+use crate::surface::{Coord, Rect};
+
+/// Direction panes are arranged in by a split.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Orientation {
+    /// Children are side by side, separated by a vertical border
+    Horizontal,
+    /// Children are stacked on top of each other, separated by a horizontal border
+    Vertical,
+}
+
+/// Side of a pane another pane can be found on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// Tiling layout of panes, each leaf holds the index of a console.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Layout {
+    Leaf(usize),
+    Split {
+        orientation: Orientation,
+        /// Share of the space given to the first child, between 0 and 1
+        ratio: f32,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Layout {
+    pub fn new(console: usize) -> Layout {
+        Layout::Leaf(console)
+    }
+
+    /// Console indices of every pane, in order from the top left.
+    pub fn leaves(&self) -> Vec<usize> {
+        match self {
+            Layout::Leaf(idx) => vec![*idx],
+            Layout::Split { first, second, .. } => {
+                let mut leaves = first.leaves();
+                leaves.extend(second.leaves());
+                leaves
+            }
+        }
+    }
+
+    pub fn contains(&self, console: usize) -> bool {
+        match self {
+            Layout::Leaf(idx) => *idx == console,
+            Layout::Split { first, second, .. } => {
+                first.contains(console) || second.contains(console)
+            }
+        }
+    }
+
+    /// Splits the pane of `target` in half, placing `console` after it.
+    pub fn split(&mut self, target: usize, console: usize, orientation: Orientation) -> bool {
+        match self {
+            Layout::Leaf(idx) if *idx == target => {
+                *self = Layout::Split {
+                    orientation,
+                    ratio: 0.5,
+                    first: Box::new(Layout::Leaf(target)),
+                    second: Box::new(Layout::Leaf(console)),
+                };
+                true
+            }
+            Layout::Leaf(_) => false,
+            Layout::Split { first, second, .. } => {
+                first.split(target, console, orientation)
+                    || second.split(target, console, orientation)
+            }
+        }
+    }
+
+    /// Removes the pane of `console`, giving its space to its sibling.
+    ///
+    /// Returns `None` if it was the last pane in the layout.
+    pub fn close(self, console: usize) -> Option<Layout> {
+        match self {
+            Layout::Leaf(idx) if idx == console => None,
+            leaf @ Layout::Leaf(_) => Some(leaf),
+            Layout::Split {
+                orientation,
+                ratio,
+                first,
+                second,
+            } => match (first.close(console), second.close(console)) {
+                (Some(first), Some(second)) => Some(Layout::Split {
+                    orientation,
+                    ratio,
+                    first: Box::new(first),
+                    second: Box::new(second),
+                }),
+                (Some(remaining), None) | (None, Some(remaining)) => Some(remaining),
+                (None, None) => None,
+            },
+        }
+    }
+
+    /// Exchanges the positions of two panes.
+    pub fn swap(&mut self, a: usize, b: usize) {
+        match self {
+            Layout::Leaf(idx) if *idx == a => *idx = b,
+            Layout::Leaf(idx) if *idx == b => *idx = a,
+            Layout::Leaf(_) => (),
+            Layout::Split { first, second, .. } => {
+                first.swap(a, b);
+                second.swap(a, b);
+            }
+        }
+    }
+
+    /// Moves every pane to the position of the next one, the last wrapping around to the first.
+    pub fn rotate(&mut self) {
+        let mut leaves = self.leaves();
+        if leaves.len() < 2 {
+            return;
+        }
+        leaves.rotate_right(1);
+        self.assign(&mut leaves.into_iter());
+    }
+
+    fn assign(&mut self, leaves: &mut impl Iterator<Item = usize>) {
+        match self {
+            Layout::Leaf(idx) => *idx = leaves.next().unwrap_or(*idx),
+            Layout::Split { first, second, .. } => {
+                first.assign(leaves);
+                second.assign(leaves);
+            }
+        }
+    }
+
+    /// Number of panes laid out side by side along `orientation`.
+    fn span(&self, orientation: Orientation) -> usize {
+        match self {
+            Layout::Split {
+                orientation: o,
+                first,
+                second,
+                ..
+            } if *o == orientation => first.span(orientation) + second.span(orientation),
+            _ => 1,
+        }
+    }
+
+    /// Gives every pane in a run of same-direction splits an equal share of the space.
+    pub fn rebalance(&mut self) {
+        if let Layout::Split {
+            orientation,
+            ratio,
+            first,
+            second,
+        } = self
+        {
+            let a = first.span(*orientation) as f32;
+            let b = second.span(*orientation) as f32;
+            *ratio = a / (a + b);
+            first.rebalance();
+            second.rebalance();
+        }
+    }
+
+    /// Finds the split whose border runs through `point`, when the layout fills `area`.
+    ///
+    /// Returns the path to the split, `false` for a first child and `true` for a second,
+    /// along with its orientation and the area it divides.
+    pub fn border_at(&self, area: Rect, point: &Coord) -> Option<(Vec<bool>, Orientation, Rect)> {
+        if let Layout::Split {
+            orientation,
+            ratio,
+            first,
+            second,
+        } = self
+        {
+            let (a, b) = split_rect(area, *orientation, *ratio);
+            let (child, side, child_area) = if a.contains(point) {
+                (first, false, a)
+            } else if b.contains(point) {
+                (second, true, b)
+            } else if area.contains(point) {
+                return Some((Vec::new(), *orientation, area));
+            } else {
+                return None;
+            };
+
+            let (mut path, orientation, area) = child.border_at(child_area, point)?;
+            path.insert(0, side);
+            return Some((path, orientation, area));
+        }
+        None
+    }
+
+    /// Changes the ratio of the split at the end of `path`, as returned by `border_at`.
+    ///
+    /// The border stops short of where it would leave a pane of the split without a cell,
+    /// when the layout fills `area`.
+    pub fn set_ratio(&mut self, area: Rect, path: &[bool], new_ratio: f32) {
+        if let Layout::Split {
+            orientation,
+            ratio,
+            first,
+            second,
+        } = self
+        {
+            let (a, b) = split_rect(area, *orientation, *ratio);
+            match path.split_first() {
+                None => (),
+                Some((false, rest)) => return first.set_ratio(a, rest, new_ratio),
+                Some((true, rest)) => return second.set_ratio(b, rest, new_ratio),
+            }
+
+            let available = match orientation {
+                Orientation::Horizontal => area.size.x,
+                Orientation::Vertical => area.size.y,
+            }
+            .saturating_sub(1);
+            if available == 0 {
+                return;
+            }
+
+            // Walk back from the requested border towards the current one until every pane fits.
+            let old = *ratio;
+            let current = (available as f32 * old).round() as isize;
+            let wanted = (available as f32 * new_ratio.clamp(0.0, 1.0)).round() as isize;
+            let step = if wanted < current { 1 } else { -1 };
+            let mut cells = wanted;
+            loop {
+                self.set_own_ratio(cells as f32 / available as f32);
+                if self.rects(area).iter().all(|(_, r)| r.size.x > 0 && r.size.y > 0) {
+                    return;
+                }
+                if cells == current {
+                    break;
+                }
+                cells += step;
+            }
+            self.set_own_ratio(old);
+        }
+    }
+
+    fn set_own_ratio(&mut self, new_ratio: f32) {
+        if let Layout::Split { ratio, .. } = self {
+            *ratio = new_ratio;
+        }
+    }
+
+    /// Computes the rectangle of every pane within `area`, leaving one cell between panes for borders.
+    pub fn rects(&self, area: Rect) -> Vec<(usize, Rect)> {
+        let mut rects = Vec::new();
+        self.collect_rects(area, &mut rects);
+        rects
+    }
+
+    fn collect_rects(&self, area: Rect, rects: &mut Vec<(usize, Rect)>) {
+        match self {
+            Layout::Leaf(idx) => rects.push((*idx, area)),
+            Layout::Split {
+                orientation,
+                ratio,
+                first,
+                second,
+            } => {
+                let (a, b) = split_rect(area, *orientation, *ratio);
+                first.collect_rects(a, rects);
+                second.collect_rects(b, rects);
+            }
+        }
+    }
+}
+
+/// Divides a rectangle in two along `orientation`, with a one cell border in between.
+pub fn split_rect(area: Rect, orientation: Orientation, ratio: f32) -> (Rect, Rect) {
+    let total = match orientation {
+        Orientation::Horizontal => area.size.x,
+        Orientation::Vertical => area.size.y,
+    };
+
+    let available = total.saturating_sub(1);
+    let first = ((available as f32 * ratio).round() as usize).min(available);
+    let second = available - first;
+
+    match orientation {
+        Orientation::Horizontal => (
+            Rect::new(area.origin, Coord { x: first, y: area.size.y }),
+            Rect::new(
+                Coord {
+                    x: area.origin.x + first + 1,
+                    y: area.origin.y,
+                },
+                Coord { x: second, y: area.size.y },
+            ),
+        ),
+        Orientation::Vertical => (
+            Rect::new(area.origin, Coord { x: area.size.x, y: first }),
+            Rect::new(
+                Coord {
+                    x: area.origin.x,
+                    y: area.origin.y + first + 1,
+                },
+                Coord { x: area.size.x, y: second },
+            ),
+        ),
+    }
+}
+
+/// Finds the pane across the border on the `direction` side of the pane of `from`.
+///
+/// When several panes share that border, the one sharing the most of it wins.
+pub fn neighbour(rects: &[(usize, Rect)], from: usize, direction: Direction) -> Option<usize> {
+    let (_, a) = rects.iter().find(|(idx, _)| *idx == from)?;
+
+    let overlap = |start: usize, len: usize, other_start: usize, other_len: usize| {
+        (start + len).min(other_start + other_len).saturating_sub(start.max(other_start))
+    };
+
+    rects
+        .iter()
+        .filter(|(idx, _)| *idx != from)
+        .filter_map(|(idx, b)| {
+            let adjacent = match direction {
+                Direction::Left => b.origin.x + b.size.x + 1 == a.origin.x,
+                Direction::Right => a.origin.x + a.size.x + 1 == b.origin.x,
+                Direction::Up => b.origin.y + b.size.y + 1 == a.origin.y,
+                Direction::Down => a.origin.y + a.size.y + 1 == b.origin.y,
+            };
+            let shared = match direction {
+                Direction::Left | Direction::Right => {
+                    overlap(a.origin.y, a.size.y, b.origin.y, b.size.y)
+                }
+                Direction::Up | Direction::Down => {
+                    overlap(a.origin.x, a.size.x, b.origin.x, b.size.x)
+                }
+            };
+            if adjacent && shared > 0 {
+                Some((*idx, shared))
+            } else {
+                None
+            }
+        })
+        .max_by_key(|(_, shared)| *shared)
+        .map(|(idx, _)| idx)
+}
+
+/// The ratio that puts the border of a split of `area` at `point`, leaving each side at least a cell.
+pub fn ratio_at(area: Rect, orientation: Orientation, point: &Coord) -> f32 {
+    let (total, offset) = match orientation {
+        Orientation::Horizontal => (area.size.x, point.x.saturating_sub(area.origin.x)),
+        Orientation::Vertical => (area.size.y, point.y.saturating_sub(area.origin.y)),
+    };
+
+    let available = total.saturating_sub(1);
+    if available < 2 {
+        return 0.5;
+    }
+    offset.max(1).min(available - 1) as f32 / available as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect::new(Coord { x, y }, Coord { x: width, y: height })
+    }
+
+    /// Console 0 on the left, console 1 above console 2 on the right.
+    fn nested() -> Layout {
+        let mut layout = Layout::new(0);
+        assert!(layout.split(0, 1, Orientation::Horizontal));
+        assert!(layout.split(1, 2, Orientation::Vertical));
+        layout
+    }
+
+    #[test]
+    fn split_leaves_a_border_column() {
+        let (a, b) = split_rect(rect(0, 0, 11, 3), Orientation::Horizontal, 0.5);
+        assert_eq!(a, rect(0, 0, 5, 3));
+        assert_eq!(b, rect(6, 0, 5, 3));
+    }
+
+    #[test]
+    fn split_of_even_size_gives_first_the_odd_cell() {
+        let (a, b) = split_rect(rect(2, 1, 10, 4), Orientation::Horizontal, 0.5);
+        assert_eq!(a, rect(2, 1, 5, 4));
+        assert_eq!(b, rect(8, 1, 4, 4));
+
+        let (a, b) = split_rect(rect(2, 1, 4, 10), Orientation::Vertical, 0.5);
+        assert_eq!(a, rect(2, 1, 4, 5));
+        assert_eq!(b, rect(2, 7, 4, 4));
+    }
+
+    #[test]
+    fn split_too_small_for_a_border() {
+        let (a, b) = split_rect(rect(0, 0, 1, 3), Orientation::Horizontal, 0.5);
+        assert_eq!(a.size, Coord { x: 0, y: 3 });
+        assert_eq!(b.size, Coord { x: 0, y: 3 });
+    }
+
+    #[test]
+    fn nested_split_rects() {
+        assert_eq!(
+            nested().rects(rect(0, 0, 21, 11)),
+            vec![
+                (0, rect(0, 0, 10, 11)),
+                (1, rect(11, 0, 10, 5)),
+                (2, rect(11, 6, 10, 5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_of_missing_pane_does_nothing() {
+        let mut layout = nested();
+        assert!(!layout.split(7, 3, Orientation::Vertical));
+        assert_eq!(layout, nested());
+    }
+
+    #[test]
+    fn closing_last_child_of_split_promotes_sibling() {
+        let layout = nested().close(2).unwrap();
+        assert_eq!(layout.leaves(), vec![0, 1]);
+        assert_eq!(
+            layout.rects(rect(0, 0, 21, 11)),
+            vec![(0, rect(0, 0, 10, 11)), (1, rect(11, 0, 10, 11))]
+        );
+
+        let layout = layout.close(0).unwrap();
+        assert_eq!(layout, Layout::Leaf(1));
+        assert_eq!(layout.close(1), None);
+    }
+
+    #[test]
+    fn swap_exchanges_positions() {
+        let mut layout = nested();
+        layout.swap(0, 2);
+        assert_eq!(layout.leaves(), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn rotate_moves_every_pane_along() {
+        let mut layout = nested();
+        layout.rotate();
+        assert_eq!(layout.leaves(), vec![2, 0, 1]);
+
+        let mut single = Layout::new(4);
+        single.rotate();
+        assert_eq!(single, Layout::Leaf(4));
+    }
+
+    #[test]
+    fn rebalance_evens_out_a_row() {
+        let mut layout = Layout::new(0);
+        layout.split(0, 1, Orientation::Horizontal);
+        layout.split(1, 2, Orientation::Horizontal);
+        assert_eq!(layout.rects(rect(0, 0, 32, 5))[0].1.size.x, 16);
+
+        layout.rebalance();
+        let widths: Vec<usize> = layout
+            .rects(rect(0, 0, 32, 5))
+            .into_iter()
+            .map(|(_, r)| r.size.x)
+            .collect();
+        assert_eq!(widths, vec![10, 10, 10]);
+    }
+
+    #[test]
+    fn neighbours_across_borders() {
+        let rects = nested().rects(rect(0, 0, 21, 11));
+        assert_eq!(neighbour(&rects, 1, Direction::Left), Some(0));
+        assert_eq!(neighbour(&rects, 1, Direction::Down), Some(2));
+        assert_eq!(neighbour(&rects, 2, Direction::Up), Some(1));
+        assert_eq!(neighbour(&rects, 0, Direction::Left), None);
+        assert_eq!(neighbour(&rects, 2, Direction::Right), None);
+    }
+
+    #[test]
+    fn neighbour_sharing_most_of_the_border_wins() {
+        let rects = vec![
+            (0, rect(0, 0, 10, 11)),
+            (1, rect(11, 0, 10, 3)),
+            (2, rect(11, 4, 10, 7)),
+        ];
+        assert_eq!(neighbour(&rects, 0, Direction::Right), Some(2));
+    }
+
+    #[test]
+    fn border_at_finds_the_split_under_the_point() {
+        let area = rect(0, 0, 21, 11);
+        let layout = nested();
+        assert_eq!(
+            layout.border_at(area, &Coord { x: 10, y: 3 }),
+            Some((vec![], Orientation::Horizontal, area))
+        );
+        assert_eq!(
+            layout.border_at(area, &Coord { x: 15, y: 5 }),
+            Some((vec![true], Orientation::Vertical, rect(11, 0, 10, 11)))
+        );
+        assert_eq!(layout.border_at(area, &Coord { x: 3, y: 3 }), None);
+        assert_eq!(layout.border_at(area, &Coord { x: 15, y: 7 }), None);
+        assert_eq!(layout.border_at(area, &Coord { x: 30, y: 3 }), None);
+        assert_eq!(Layout::new(0).border_at(area, &Coord { x: 10, y: 3 }), None);
+    }
+
+    #[test]
+    fn ratio_at_leaves_each_side_a_cell() {
+        let area = rect(10, 2, 21, 11);
+        let at = |x, y| Coord { x, y };
+        assert_eq!(ratio_at(area, Orientation::Horizontal, &at(20, 0)), 0.5);
+        assert_eq!(ratio_at(area, Orientation::Horizontal, &at(0, 0)), 0.05);
+        assert_eq!(ratio_at(area, Orientation::Horizontal, &at(40, 0)), 0.95);
+        assert_eq!(ratio_at(area, Orientation::Vertical, &at(0, 7)), 0.5);
+        assert_eq!(ratio_at(rect(0, 0, 2, 2), Orientation::Vertical, &at(0, 0)), 0.5);
+    }
+
+    #[test]
+    fn set_ratio_moves_the_border() {
+        let area = rect(0, 0, 21, 11);
+        let mut layout = nested();
+        layout.set_ratio(area, &[true], 0.2);
+        assert_eq!(
+            layout.rects(area),
+            vec![
+                (0, rect(0, 0, 10, 11)),
+                (1, rect(11, 0, 10, 2)),
+                (2, rect(11, 3, 10, 8)),
+            ]
+        );
+
+        layout.set_ratio(area, &[], 2.0);
+        assert_eq!(layout.rects(area)[0].1, rect(0, 0, 19, 11));
+    }
+
+    #[test]
+    fn set_ratio_keeps_nested_panes_a_cell() {
+        let area = rect(0, 0, 21, 5);
+        let mut layout = Layout::new(0);
+        layout.split(0, 1, Orientation::Horizontal);
+        layout.split(1, 2, Orientation::Horizontal);
+
+        layout.set_ratio(area, &[], 1.0);
+        assert_eq!(
+            layout.rects(area),
+            vec![
+                (0, rect(0, 0, 17, 5)),
+                (1, rect(18, 0, 1, 5)),
+                (2, rect(20, 0, 1, 5)),
+            ]
+        );
+
+        layout.set_ratio(area, &[true], 0.0);
+        layout.set_ratio(area, &[], 0.0);
+        assert!(layout.rects(area).iter().all(|(_, r)| r.size.x > 0));
+        assert_eq!(layout.rects(area)[0].1, rect(0, 0, 1, 5));
+    }
+}
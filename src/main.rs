@@ -8,6 +8,7 @@
 // extern crate widestring;
 // extern crate winapi;
 
+use std::env;
 use std::io::{stdout, Write};
 use std::path::PathBuf;
 use std::process::exit;
@@ -15,19 +16,49 @@ use std::fs::OpenOptions;
 
 
 mod buffer;
+mod command;
+mod compositor;
+mod config;
+mod copy;
+#[cfg(windows)]
 mod conpty;
 mod context;
 mod event;
+mod keys;
+mod layout;
+#[cfg(test)]
+mod mockpty;
+mod mouse;
+mod output;
+mod paste;
+#[cfg(windows)]
 mod pipes;
 mod pty;
+mod search;
 mod surface;
+mod tabs;
+#[cfg(unix)]
+mod unixcon;
+#[cfg(unix)]
+mod unixpty;
+#[cfg(windows)]
 mod wincon;
 mod ansitypes;
 
+#[cfg(windows)]
 use self::conpty::*;
+use self::compositor::*;
+use self::config::Config;
 use self::context::*;
 use self::event::*;
+use self::layout::Orientation;
+use self::mouse::register_mouse_handler;
 use self::surface::{Coord, Surface};
+#[cfg(unix)]
+use self::unixcon::*;
+#[cfg(unix)]
+use self::unixpty::*;
+#[cfg(windows)]
 use self::wincon::*;
 
 use terminal_size::{terminal_size, Height, Width};
@@ -36,14 +67,35 @@ use terminal_size::{terminal_size, Height, Width};
 #[allow(unused)]
 
 fn main() {
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("conmux: invalid config\n{}", e);
+            exit(1);
+        }
+    };
+
     let term = Surface::new();
 
     let token = enable_console().unwrap();
     let mut ectx = EventContext::new(token);
 
-    let mut pty =
-        ConPty::new(&term.dimensions, "powershell", Some(&PathBuf::from("C:\\"))).unwrap();
-    ectx.add_console(pty);
+    #[cfg(windows)]
+    ectx.console_factory(|size| ConPty::new(size, "powershell", Some(&PathBuf::from("C:\\"))));
+
+    #[cfg(unix)]
+    ectx.console_factory(|size| {
+        UnixPty::new(
+            size,
+            env::var("SHELL").unwrap_or_else(|_| String::from("/bin/sh")),
+            env::var_os("HOME").map(PathBuf::from).as_ref().map(|p| p.as_path()),
+        )
+    });
+
+    ectx.context_mut().resize(term.dimensions);
+    ectx.context_mut().set_scrollback_limit(config.scrollback);
+    ectx.context_mut().set_mode_keys(config.mode_keys);
+    ectx.context_mut().split(Orientation::Horizontal).unwrap();
     ectx.sender(|tx| {
         let (Width(mut w), Height(mut h)) = terminal_size().unwrap();
         loop {
@@ -51,13 +103,10 @@ fn main() {
             if (new_h != h) || (new_w != w) {
                 w = new_w;
                 h = new_h;
-                tx.send(Action::PtyResize(
-                    0,
-                    Coord {
-                        x: new_w as usize,
-                        y: new_h as usize,
-                    },
-                ));
+                tx.send(Action::HostResize(Coord {
+                    x: new_w as usize,
+                    y: new_h as usize,
+                }));
             }
         }
         Ok(())
@@ -71,19 +120,11 @@ fn main() {
     });
 
     ectx.handler(|ctx, action| {
-        if let Action::PtyDead(_) = action {
-            restore_console();
-            exit(0);
-        }
-        None
-    });
-
-    ectx.handler(|ctx, action| {
-        let mut stdout = stdout();
-        let mut lock = stdout.lock();
-        if let Action::PtyOutReceived(_, c) = action {
-            lock.write(&[c]);
-            lock.flush();
+        if let Action::PtyDead(idx) = action {
+            ctx.delete_console(idx);
+            if ctx.is_empty() {
+                ctx.quit();
+            }
         }
         None
     });
@@ -98,6 +139,12 @@ fn main() {
     // }); 
 
     listen_input(&mut ectx);
-    register_console_handler(&mut ectx);
+    register_console_handler(&mut ectx, config.bindings);
+    register_mouse_handler(&mut ectx);
+    register_render_handler(&mut ectx);
     ectx.start_event_loop();
+
+    // Drop the consoles before giving the terminal back, so their shells are gone.
+    drop(ectx);
+    restore_console();
 }
@@ -0,0 +1,186 @@
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::pty::{KeepAlive, PseudoConsole};
+use crate::surface::Coord;
+
+/// A single step of the output a `MockPty` replays to the multiplexer.
+#[derive(Debug, Clone)]
+pub enum ScriptStep {
+    /// Bytes the console writes, as if the shell had printed them
+    Output(Vec<u8>),
+    /// Pause the reader before continuing with the script
+    Delay(Duration),
+}
+
+/// Scripted in-memory pseudoconsole, used to drive handlers without a real shell.
+///
+/// The reader replays the script and then reports EOF, the writer records every
+/// byte the multiplexer sends, and the keep alive only dies when told to through
+/// a `MockPtyHandle`.
+pub struct MockPty {
+    size: Coord,
+    pub pipes: (MockPtyReader, MockPtyWriter),
+    state: Arc<MockPtyState>,
+}
+
+struct MockPtyState {
+    written: Mutex<Vec<u8>>,
+    resizes: Mutex<Vec<Coord>>,
+    started: AtomicBool,
+    dead: AtomicBool,
+}
+
+impl MockPty {
+    pub fn new(coord: impl Into<Coord>, script: Vec<ScriptStep>) -> MockPty {
+        let state = Arc::new(MockPtyState {
+            written: Mutex::new(Vec::new()),
+            resizes: Mutex::new(Vec::new()),
+            started: AtomicBool::new(false),
+            dead: AtomicBool::new(false),
+        });
+
+        MockPty {
+            size: coord.into(),
+            pipes: (
+                MockPtyReader {
+                    script: Arc::new(Mutex::new(script.into_iter().collect())),
+                },
+                MockPtyWriter {
+                    state: state.clone(),
+                },
+            ),
+            state,
+        }
+    }
+
+    /// Returns a handle that outlives the console being moved into an `EventContext`.
+    pub fn handle(&self) -> MockPtyHandle {
+        MockPtyHandle {
+            state: self.state.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MockPtyHandle {
+    state: Arc<MockPtyState>,
+}
+
+impl MockPtyHandle {
+    /// Everything written to the console so far.
+    pub fn written(&self) -> Vec<u8> {
+        self.state.written.lock().unwrap().clone()
+    }
+
+    /// Every size the console was resized to, in order.
+    pub fn resizes(&self) -> Vec<Coord> {
+        self.state.resizes.lock().unwrap().clone()
+    }
+
+    pub fn started(&self) -> bool {
+        self.state.started.load(Ordering::SeqCst)
+    }
+
+    /// Makes the console's keep alive report the shell as dead.
+    pub fn kill(&self) {
+        self.state.dead.store(true, Ordering::SeqCst);
+    }
+}
+
+#[derive(Clone)]
+pub struct MockPtyReader {
+    script: Arc<Mutex<VecDeque<ScriptStep>>>,
+}
+
+impl Read for MockPtyReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            // Don't hold the lock while sleeping, clones of the reader share the script.
+            let step = self.script.lock().unwrap().pop_front();
+            match step {
+                None => return Ok(0),
+                Some(ScriptStep::Delay(duration)) => thread::sleep(duration),
+                Some(ScriptStep::Output(mut bytes)) => {
+                    if bytes.len() > buf.len() {
+                        let rest = bytes.split_off(buf.len());
+                        self.script
+                            .lock()
+                            .unwrap()
+                            .push_front(ScriptStep::Output(rest));
+                    }
+                    buf[..bytes.len()].copy_from_slice(&bytes);
+                    return Ok(bytes.len());
+                }
+            }
+        }
+    }
+}
+
+pub struct MockPtyWriter {
+    state: Arc<MockPtyState>,
+}
+
+impl Write for MockPtyWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.state.dead.load(Ordering::SeqCst) {
+            return Err(Error::new(ErrorKind::BrokenPipe, "Console is dead"));
+        }
+        self.state.written.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct MockPtyKeepAlive {
+    state: Arc<MockPtyState>,
+}
+
+impl KeepAlive for MockPtyKeepAlive {
+    fn dead(&self) -> bool {
+        self.state.dead.load(Ordering::SeqCst)
+    }
+}
+
+impl PseudoConsole<MockPty> for MockPty {
+    type Reader = MockPtyReader;
+    type Writer = MockPtyWriter;
+    type KeepAlive = MockPtyKeepAlive;
+
+    fn dimensions(&self) -> &Coord {
+        &self.size
+    }
+
+    fn resize(&mut self, coord: &Coord) -> Result<&Coord> {
+        self.state.resizes.lock().unwrap().push(*coord);
+        self.size = *coord;
+        Ok(self.dimensions())
+    }
+
+    fn start_shell(&mut self) -> Result<()> {
+        self.state.started.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn writer(&mut self) -> &mut Self::Writer {
+        &mut self.pipes.1
+    }
+
+    fn reader(&self) -> Result<Self::Reader> {
+        Ok(self.pipes.0.clone())
+    }
+
+    fn keep_alive(&self) -> Self::KeepAlive {
+        MockPtyKeepAlive {
+            state: self.state.clone(),
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use crate::ansitypes::*;
+use crate::buffer::Buffer;
+use crate::event::{Action, EventContext};
+use crate::keys::Modifiers;
+use crate::layout::{ratio_at, Orientation};
+use crate::pty::PseudoConsole;
+use crate::surface::{Coord, Rect};
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Lines the wheel scrolls through the history of a pane that doesn't take the mouse itself.
+const WHEEL_SCROLL_LINES: isize = 3;
+
+/// How often consoles are resized while a border is being dragged.
+const DRAG_RESIZE_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    /// No button is held, only sent for motion
+    None,
+    WheelUp,
+    WheelDown,
+    WheelLeft,
+    WheelRight,
+    /// One of the extra buttons 8 to 11, like back and forward
+    Extra(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseEventKind {
+    Press,
+    Release,
+    /// The pointer moved, with `button` held if it isn't `MouseButton::None`
+    Motion,
+}
+
+/// A mouse event reported by the host, positioned in cells from the top left of the surface.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub kind: MouseEventKind,
+    pub button: MouseButton,
+    pub position: Coord,
+    pub modifiers: Modifiers,
+}
+
+impl MouseEvent {
+    /// Decodes the parameters of an SGR mouse report, `CSI < b ; x ; y M` or `m` for a release.
+    pub fn from_sgr(params: &[i64], release: bool) -> Option<MouseEvent> {
+        let (code, x, y) = match params {
+            [code, x, y] => (*code, *x, *y),
+            _ => return None,
+        };
+
+        let button = match (code & 0b11, code & 0b1100_0000) {
+            (0, 0) => MouseButton::Left,
+            (1, 0) => MouseButton::Middle,
+            (2, 0) => MouseButton::Right,
+            (3, 0) => MouseButton::None,
+            (0, 64) => MouseButton::WheelUp,
+            (1, 64) => MouseButton::WheelDown,
+            (2, 64) => MouseButton::WheelLeft,
+            (3, 64) => MouseButton::WheelRight,
+            (n, 128) => MouseButton::Extra(8 + n as u8),
+            _ => return None,
+        };
+
+        let kind = if release {
+            MouseEventKind::Release
+        } else if code & 32 != 0 {
+            MouseEventKind::Motion
+        } else {
+            MouseEventKind::Press
+        };
+
+        Some(MouseEvent {
+            kind,
+            button,
+            position: Coord {
+                x: (x.max(1) - 1) as usize,
+                y: (y.max(1) - 1) as usize,
+            },
+            modifiers: Modifiers {
+                shift: code & 4 != 0,
+                alt: code & 8 != 0,
+                ctrl: code & 16 != 0,
+            },
+        })
+    }
+
+    /// The same event relative to the top left of `rect`, held inside it.
+    pub fn relative_to(&self, rect: &Rect) -> MouseEvent {
+        let clamp = |p: usize, origin: usize, size: usize| {
+            p.saturating_sub(origin).min(size.saturating_sub(1))
+        };
+
+        MouseEvent {
+            position: Coord {
+                x: clamp(self.position.x, rect.origin.x, rect.size.x),
+                y: clamp(self.position.y, rect.origin.y, rect.size.y),
+            },
+            ..*self
+        }
+    }
+
+    /// Whether a console with the modes of `buffer` asked to be told about this event.
+    pub fn reported(&self, buffer: &Buffer) -> bool {
+        match (self.kind, self.button) {
+            (MouseEventKind::Motion, MouseButton::None) => buffer.mode(Mode::ReportAllMouseMotion),
+            (MouseEventKind::Motion, _) => {
+                buffer.mode(Mode::ReportCellMouseMotion) || buffer.mode(Mode::ReportAllMouseMotion)
+            }
+            _ => {
+                buffer.mode(Mode::ReportMouseClicks)
+                    || buffer.mode(Mode::ReportCellMouseMotion)
+                    || buffer.mode(Mode::ReportAllMouseMotion)
+            }
+        }
+    }
+
+    /// Encodes the event in the format the console with the modes of `buffer` asked for.
+    ///
+    /// Returns `None` if the position can't be encoded, which only happens for the
+    /// legacy encodings on large surfaces.
+    pub fn encode(&self, buffer: &Buffer) -> Option<Vec<u8>> {
+        let mut code = match self.button {
+            MouseButton::Left => 0,
+            MouseButton::Middle => 1,
+            MouseButton::Right => 2,
+            MouseButton::None => 3,
+            MouseButton::WheelUp => 64,
+            MouseButton::WheelDown => 65,
+            MouseButton::WheelLeft => 66,
+            MouseButton::WheelRight => 67,
+            MouseButton::Extra(n) => 128 + (n.saturating_sub(8) & 0b11) as usize,
+        };
+        if self.kind == MouseEventKind::Motion {
+            code += 32;
+        }
+        if self.modifiers.shift {
+            code += 4;
+        }
+        if self.modifiers.alt {
+            code += 8;
+        }
+        if self.modifiers.ctrl {
+            code += 16;
+        }
+
+        let x = self.position.x + 1;
+        let y = self.position.y + 1;
+
+        if buffer.mode(Mode::SgrMouse) {
+            let end = if self.kind == MouseEventKind::Release { 'm' } else { 'M' };
+            return Some(format!("\x1b[<{};{};{}{}", code, x, y, end).into_bytes());
+        }
+
+        // The other encodings can't tell which button was released.
+        if self.kind == MouseEventKind::Release {
+            code = (code & !0b11) | 3;
+        }
+
+        if buffer.mode(Mode::UrxvtMouse) {
+            return Some(format!("\x1b[{};{};{}M", code + 32, x, y).into_bytes());
+        }
+
+        let mut out = b"\x1b[M".to_vec();
+        if buffer.mode(Mode::Utf8Mouse) {
+            for value in &[code + 32, x + 32, y + 32] {
+                let c = std::char::from_u32(*value as u32).filter(|_| *value < 2048)?;
+                let mut buf = [0; 4];
+                out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        } else {
+            if x + 32 > 255 || y + 32 > 255 {
+                return None;
+            }
+            out.extend_from_slice(&[(code + 32) as u8, (x + 32) as u8, (y + 32) as u8]);
+        }
+        Some(out)
+    }
+}
+
+/// A border between panes being dragged with the mouse.
+struct BorderDrag {
+    /// Path to the split in the layout of the active tab
+    path: Vec<bool>,
+    orientation: Orientation,
+    /// Area divided by the split
+    area: Rect,
+    last_resize: Instant,
+}
+
+/// Focuses panes that are clicked on, and reports mouse events to panes that asked for them.
+///
+/// Once a button is pressed on a pane, the events up to its release go to that pane even if
+/// the pointer leaves it, like a host terminal does. Pressing on a border between panes
+/// drags it instead.
+pub fn register_mouse_handler<T>(ectx: &mut EventContext<T>)
+where
+    T: PseudoConsole<T>,
+{
+    let mut captured: Option<usize> = None;
+    let mut drag: Option<BorderDrag> = None;
+
+    ectx.handler(move |ctx, action| {
+        let event = match action {
+            Action::HostMouse(event) => event,
+            Action::HostResize(_) => {
+                drag = None;
+                return None;
+            }
+            _ => return None,
+        };
+
+        if let Some(border) = drag.as_mut() {
+            match event.kind {
+                MouseEventKind::Motion => {
+                    let ratio = ratio_at(border.area, border.orientation, &event.position);
+                    ctx.set_split_ratio(&border.path, ratio);
+                    if border.last_resize.elapsed() >= DRAG_RESIZE_INTERVAL {
+                        border.last_resize = Instant::now();
+                        ctx.relayout();
+                    }
+                }
+                MouseEventKind::Release => {
+                    drag = None;
+                    ctx.relayout();
+                }
+                MouseEventKind::Press => (),
+            }
+            return None;
+        }
+
+        let rects = ctx.pane_rects();
+        let target = captured
+            .and_then(|idx| rects.iter().find(|(i, _)| *i == idx))
+            .or_else(|| rects.iter().find(|(_, rect)| rect.contains(&event.position)))
+            .cloned();
+
+        let (idx, rect) = match target {
+            Some(target) => target,
+            None => {
+                captured = None;
+                if event.kind == MouseEventKind::Press
+                    && event.button == MouseButton::Left
+                    && !ctx.zoomed()
+                {
+                    let border = ctx.layout()?.border_at(ctx.pane_area(), &event.position);
+                    if let Some((path, orientation, area)) = border {
+                        drag = Some(BorderDrag {
+                            path,
+                            orientation,
+                            area,
+                            last_resize: Instant::now(),
+                        });
+                    }
+                }
+                return None;
+            }
+        };
+
+        match (event.kind, event.button) {
+            (_, MouseButton::WheelUp)
+            | (_, MouseButton::WheelDown)
+            | (_, MouseButton::WheelLeft)
+            | (_, MouseButton::WheelRight) => (),
+            (MouseEventKind::Press, _) => {
+                captured = Some(idx);
+                if idx != ctx.active_console_index() {
+                    ctx.set_active_console(idx).ok();
+                }
+            }
+            (MouseEventKind::Release, _) => captured = None,
+            (MouseEventKind::Motion, _) => (),
+        }
+
+        let event = event.relative_to(&rect);
+        let encoded = {
+            let buffer = ctx.buffered_console(idx)?.buffer();
+            if !event.reported(buffer) {
+                let lines = match (event.kind, event.button) {
+                    (MouseEventKind::Press, MouseButton::WheelUp) => WHEEL_SCROLL_LINES,
+                    (MouseEventKind::Press, MouseButton::WheelDown) => -WHEEL_SCROLL_LINES,
+                    _ => return None,
+                };
+                ctx.buffered_console_mut(idx)?.buffer_mut().scroll_view(lines);
+                ctx.mark_dirty();
+                return None;
+            }
+            event.encode(buffer)?
+        };
+
+        let writer = ctx.console_mut(idx)?.writer();
+        writer.write_all(&encoded).ok();
+        writer.flush().ok();
+        None
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sgr_buffer(modes: &[Mode]) -> Buffer {
+        let mut buffer = Buffer::new(Coord { x: 80, y: 24 });
+        buffer.set_mode(Mode::SgrMouse);
+        for mode in modes {
+            buffer.set_mode(*mode);
+        }
+        buffer
+    }
+
+    #[test]
+    fn extra_buttons_round_trip() {
+        for (code, button) in &[
+            (66, MouseButton::WheelLeft),
+            (67, MouseButton::WheelRight),
+            (128, MouseButton::Extra(8)),
+            (131, MouseButton::Extra(11)),
+        ] {
+            let event = MouseEvent::from_sgr(&[*code, 3, 4], false).unwrap();
+            assert_eq!(event.button, *button);
+            let buffer = sgr_buffer(&[Mode::ReportMouseClicks]);
+            let expected = format!("\x1b[<{};3;4M", code).into_bytes();
+            assert_eq!(event.encode(&buffer), Some(expected));
+        }
+    }
+
+    #[test]
+    fn motion_is_only_reported_to_panes_that_asked() {
+        let hover = MouseEvent::from_sgr(&[35, 1, 1], false).unwrap();
+        let drag = MouseEvent::from_sgr(&[32, 1, 1], false).unwrap();
+        assert_eq!(hover.kind, MouseEventKind::Motion);
+        assert_eq!(hover.button, MouseButton::None);
+
+        let clicks = sgr_buffer(&[Mode::ReportMouseClicks]);
+        assert!(!hover.reported(&clicks) && !drag.reported(&clicks));
+        let cells = sgr_buffer(&[Mode::ReportCellMouseMotion]);
+        assert!(!hover.reported(&cells) && drag.reported(&cells));
+        let all = sgr_buffer(&[Mode::ReportAllMouseMotion]);
+        assert!(hover.reported(&all) && drag.reported(&all));
+    }
+}
@@ -0,0 +1,587 @@
+use crate::ansitypes::*;
+use crate::event::{Action, Column, Line, PtyIndex};
+
+use crossbeam::channel::Sender;
+use std::str;
+use vte::Perform;
+
+/// Turns the output of a pseudoconsole into `Pty*` actions tagged with its index.
+pub struct OutputPerformer {
+    idx: PtyIndex,
+    tx: Sender<Action>,
+}
+
+impl OutputPerformer {
+    pub fn new(idx: PtyIndex, tx: Sender<Action>) -> OutputPerformer {
+        OutputPerformer { idx, tx }
+    }
+
+    fn send(&self, action: Action) {
+        // The event loop may already be gone if the console outlives it.
+        self.tx.send(action).ok();
+    }
+}
+
+/// Parses console output into the actions it turns into, for feeding buffers in tests.
+#[cfg(test)]
+pub fn parse(bytes: &[u8]) -> Vec<Action> {
+    let (tx, rx) = crossbeam::channel::unbounded();
+    let mut performer = OutputPerformer::new(PtyIndex(0), tx);
+    let mut parser = vte::Parser::new();
+    for byte in bytes {
+        parser.advance(&mut performer, *byte);
+    }
+    drop(performer);
+    rx.iter().collect()
+}
+
+/// Parses SGR parameters into attributes, following extended colors.
+pub fn parse_sgr_attrs(params: &[i64]) -> Vec<Attr> {
+    let mut attrs = Vec::new();
+    let mut i = 0;
+
+    if params.is_empty() {
+        attrs.push(Attr::Reset);
+        return attrs;
+    }
+
+    while i < params.len() {
+        let attr = match params[i] {
+            0 => Some(Attr::Reset),
+            1 => Some(Attr::Bold),
+            2 => Some(Attr::Dim),
+            3 => Some(Attr::Italic),
+            4 => Some(Attr::Underscore),
+            5 => Some(Attr::BlinkSlow),
+            6 => Some(Attr::BlinkFast),
+            7 => Some(Attr::Reverse),
+            8 => Some(Attr::Hidden),
+            9 => Some(Attr::Strike),
+            21 => Some(Attr::CancelBold),
+            22 => Some(Attr::CancelBoldDim),
+            23 => Some(Attr::CancelItalic),
+            24 => Some(Attr::CancelUnderline),
+            25 => Some(Attr::CancelBlink),
+            27 => Some(Attr::CancelReverse),
+            28 => Some(Attr::CancelHidden),
+            29 => Some(Attr::CancelStrike),
+            n @ 30..=37 => Some(Attr::Foreground(Color::Named(named_color(n - 30)))),
+            38 => {
+                let mut start = 0;
+                let color = parse_color(&params[i..], &mut start);
+                i += start;
+                color.map(Attr::Foreground)
+            }
+            39 => Some(Attr::Foreground(Color::Named(NamedColor::Foreground))),
+            n @ 40..=47 => Some(Attr::Background(Color::Named(named_color(n - 40)))),
+            48 => {
+                let mut start = 0;
+                let color = parse_color(&params[i..], &mut start);
+                i += start;
+                color.map(Attr::Background)
+            }
+            49 => Some(Attr::Background(Color::Named(NamedColor::Background))),
+            n @ 90..=97 => Some(Attr::Foreground(Color::Named(named_color(n - 90).to_bright()))),
+            n @ 100..=107 => {
+                Some(Attr::Background(Color::Named(named_color(n - 100).to_bright())))
+            }
+            _ => None,
+        };
+
+        if let Some(attr) = attr {
+            attrs.push(attr);
+        }
+
+        i += 1;
+    }
+
+    attrs
+}
+
+fn named_color(n: i64) -> NamedColor {
+    match n {
+        0 => NamedColor::Black,
+        1 => NamedColor::Red,
+        2 => NamedColor::Green,
+        3 => NamedColor::Yellow,
+        4 => NamedColor::Blue,
+        5 => NamedColor::Magenta,
+        6 => NamedColor::Cyan,
+        _ => NamedColor::White,
+    }
+}
+
+/// Parses `38;5;n` and `38;2;r;g;b`, leaving `i` on the last parameter consumed.
+fn parse_color(attrs: &[i64], i: &mut usize) -> Option<Color> {
+    if attrs.len() < 2 {
+        return None;
+    }
+
+    match attrs[*i + 1] {
+        2 => {
+            // RGB color spec.
+            if attrs.len() < 5 {
+                return None;
+            }
+
+            let r = attrs[*i + 2];
+            let g = attrs[*i + 3];
+            let b = attrs[*i + 4];
+
+            *i += 4;
+
+            let range = 0..256;
+            if !range.contains(&r) || !range.contains(&g) || !range.contains(&b) {
+                return None;
+            }
+
+            Some(Color::Spec(r as u8, g as u8, b as u8))
+        }
+        5 => {
+            if attrs.len() < 3 {
+                return None;
+            }
+
+            *i += 2;
+            let idx = attrs[*i];
+            match idx {
+                0..=255 => Some(Color::Indexed(idx as u8)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+impl Perform for OutputPerformer {
+    fn print(&mut self, c: char) {
+        self.send(Action::PtyOutput(self.idx, c));
+    }
+
+    fn execute(&mut self, byte: u8) {
+        let idx = self.idx;
+        let action = match byte {
+            C0::HT => Action::PtyPutTabs(idx, 1),
+            C0::BS => Action::PtyBackspace(idx),
+            C0::CR => Action::PtyCarriageReturn(idx),
+            C0::LF | C0::VT | C0::FF => Action::PtyLineFeed(idx),
+            C0::BEL => Action::PtyBell(idx),
+            C0::SUB => Action::PtySubtitute(idx),
+            C0::SI => Action::PtySetActiveCharset(idx, CharsetIndex::G0),
+            C0::SO => Action::PtySetActiveCharset(idx, CharsetIndex::G1),
+            C1::SS2 => Action::PtySingleShift(idx, CharsetIndex::G2),
+            C1::SS3 => Action::PtySingleShift(idx, CharsetIndex::G3),
+            C1::NEL => Action::PtyNewline(idx),
+            C1::HTS => Action::PtySetHorizontalTabstop(idx),
+            _ => return,
+        };
+        self.send(action);
+    }
+
+    fn hook(&mut self, _params: &[i64], _intermediates: &[u8], _ignore: bool) {}
+
+    fn put(&mut self, _byte: u8) {}
+
+    fn unhook(&mut self) {}
+
+    fn osc_dispatch(&mut self, params: &[&[u8]]) {
+        let idx = self.idx;
+        if params.is_empty() || params[0].is_empty() {
+            return;
+        }
+
+        match params[0] {
+            // Set window title, with or without the icon name.
+            b"0" | b"2" => {
+                if params.len() > 1 {
+                    // Titles may contain semicolons, which were split as parameters.
+                    let title = params[1..]
+                        .iter()
+                        .map(|p| String::from_utf8_lossy(p))
+                        .collect::<Vec<_>>()
+                        .join(";");
+                    self.send(Action::PtySetTitle(idx, title));
+                }
+            }
+            // Set indexed colors, in pairs of index and spec.
+            b"4" => {
+                for chunk in params[1..].chunks(2) {
+                    if chunk.len() < 2 {
+                        break;
+                    }
+                    let index = parse_number(chunk[0]);
+                    let color = parse_rgb_color(chunk[1]);
+                    if let (Some(index), Some(color)) = (index, color) {
+                        self.send(Action::PtySetColor(idx, index as usize, color));
+                    }
+                }
+            }
+            // Reset indexed colors, all of them if none are given.
+            b"104" => {
+                if params.len() == 1 {
+                    self.send(Action::PtyResetColors(idx));
+                } else {
+                    for param in &params[1..] {
+                        if let Some(index) = parse_number(param) {
+                            self.send(Action::PtyResetColor(idx, index as usize));
+                        }
+                    }
+                }
+            }
+            // Set the clipboard, the selection parameter is ignored and queries aren't answered.
+            b"52" => {
+                if params.len() > 2 && params[2] != b"?" {
+                    if let Some(data) = decode_base64(params[2]) {
+                        self.send(Action::PtySetClipboard(idx, data));
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &[i64], intermediates: &[u8], _ignore: bool, c: char) {
+        macro_rules! arg_or_default {
+            (idx: $idx:expr, default: $default:expr) => {
+                params
+                    .get($idx)
+                    .and_then(|v| if *v == 0 { None } else { Some(*v) })
+                    .unwrap_or($default)
+            };
+        }
+
+        let idx = self.idx;
+        let private = intermediates.get(0) == Some(&b'?');
+
+        let action = match c {
+            '@' => Action::PtyInsertBlank(idx, Column(arg_or_default!(idx: 0, default: 1) as usize)),
+            'A' => Action::PtyCursorMoveUp(idx, Line(arg_or_default!(idx: 0, default: 1) as usize)),
+            'B' | 'e' => {
+                Action::PtyCursorMoveDown(idx, Line(arg_or_default!(idx: 0, default: 1) as usize))
+            }
+            'C' | 'a' => Action::PtyCursorMoveForward(
+                idx,
+                Column(arg_or_default!(idx: 0, default: 1) as usize),
+            ),
+            'D' => Action::PtyCursorMoveBackward(
+                idx,
+                Column(arg_or_default!(idx: 0, default: 1) as usize),
+            ),
+            'E' => Action::PtyCursorMoveDownAndCarriageReturn(
+                idx,
+                Column(arg_or_default!(idx: 0, default: 1) as usize),
+            ),
+            'F' => Action::PtyCursorMoveUpAndCarriageReturn(
+                idx,
+                Column(arg_or_default!(idx: 0, default: 1) as usize),
+            ),
+            'G' | '`' => Action::PtyCursorGotoColumn(
+                idx,
+                Column(arg_or_default!(idx: 0, default: 1) as usize - 1),
+            ),
+            'H' | 'f' => {
+                let y = arg_or_default!(idx: 0, default: 1) as usize;
+                let x = arg_or_default!(idx: 1, default: 1) as usize;
+                Action::PtyCursorGoto(idx, Line(y - 1), Column(x - 1))
+            }
+            'I' => Action::PtyCursorMoveForwardTabs(
+                idx,
+                Column(arg_or_default!(idx: 0, default: 1) as usize),
+            ),
+            'J' => {
+                let mode = match params.get(0).cloned().unwrap_or(0) {
+                    0 => ClearMode::Below,
+                    1 => ClearMode::Above,
+                    2 => ClearMode::All,
+                    3 => ClearMode::Saved,
+                    _ => return,
+                };
+                Action::PtyClearScreen(idx, mode)
+            }
+            'K' => {
+                let mode = match params.get(0).cloned().unwrap_or(0) {
+                    0 => LineClearMode::Right,
+                    1 => LineClearMode::Left,
+                    2 => LineClearMode::All,
+                    _ => return,
+                };
+                Action::PtyClearLine(idx, mode)
+            }
+            'L' => Action::PtyInsertBlankLines(idx, Line(arg_or_default!(idx: 0, default: 1) as usize)),
+            'M' => Action::PtyDeleteLines(idx, Line(arg_or_default!(idx: 0, default: 1) as usize)),
+            'P' => Action::PtyDeleteCharacters(
+                idx,
+                Column(arg_or_default!(idx: 0, default: 1) as usize),
+            ),
+            'S' => Action::PtyVtScrollUp(idx, Line(arg_or_default!(idx: 0, default: 1) as usize)),
+            'T' => Action::PtyVtScrollDown(idx, Line(arg_or_default!(idx: 0, default: 1) as usize)),
+            'X' => Action::PtyEraseCharacters(
+                idx,
+                Column(arg_or_default!(idx: 0, default: 1) as usize),
+            ),
+            'Z' => Action::PtyCursorMoveBackwardsTabs(
+                idx,
+                Column(arg_or_default!(idx: 0, default: 1) as usize),
+            ),
+            'd' => Action::PtyCursorGotoLine(idx, Line(arg_or_default!(idx: 0, default: 1) as usize - 1)),
+            'g' => {
+                let mode = match params.get(0).cloned().unwrap_or(0) {
+                    0 => TabulationClearMode::Current,
+                    3 => TabulationClearMode::All,
+                    _ => return,
+                };
+                Action::PtyClearTabs(idx, mode)
+            }
+            'h' => {
+                for arg in params {
+                    if let Some(mode) = Mode::from_primitive(private, *arg) {
+                        self.send(Action::PtySetMode(idx, mode));
+                    }
+                }
+                return;
+            }
+            'l' => {
+                for arg in params {
+                    if let Some(mode) = Mode::from_primitive(private, *arg) {
+                        self.send(Action::PtyUnsetMode(idx, mode));
+                    }
+                }
+                return;
+            }
+            'm' if intermediates.is_empty() => {
+                for attr in parse_sgr_attrs(params) {
+                    self.send(Action::PtyTerminalAttribute(idx, attr));
+                }
+                return;
+            }
+            'q' if intermediates.get(0) == Some(&b' ') => Action::PtySetCursorStyle(idx),
+            'r' if !private => {
+                // Without a bottom margin the region runs to the bottom of the screen.
+                let top = arg_or_default!(idx: 0, default: 1) as usize;
+                let bottom = arg_or_default!(idx: 1, default: i64::max_value()) as usize;
+                Action::PtySetScrollingRegion(idx, Line(top - 1)..Line(bottom))
+            }
+            's' if intermediates.is_empty() => Action::PtyCursorSavePosition(idx),
+            'u' if intermediates.is_empty() => Action::PtyCursorRestorePosition(idx),
+            _ => return,
+        };
+
+        self.send(action);
+    }
+
+    fn esc_dispatch(&mut self, _params: &[i64], intermediates: &[u8], _ignore: bool, byte: u8) {
+        let idx = self.idx;
+
+        macro_rules! configure_charset {
+            ($charset:path) => {{
+                let index: CharsetIndex = match intermediates.get(0) {
+                    Some(b'(') => CharsetIndex::G0,
+                    Some(b')') => CharsetIndex::G1,
+                    Some(b'*') => CharsetIndex::G2,
+                    Some(b'+') => CharsetIndex::G3,
+                    _ => return,
+                };
+                Action::PtyConfigureCharset(idx, index, $charset)
+            }};
+        }
+
+        let action = match (byte, intermediates.get(0)) {
+            (b'B', _) => configure_charset!(StandardCharset::Ascii),
+            (b'D', None) => Action::PtyLineFeed(idx),
+            (b'E', None) => Action::PtyNewline(idx),
+            (b'H', None) => Action::PtySetHorizontalTabstop(idx),
+            (b'M', None) => Action::PtyReverseIndex(idx),
+            (b'N', None) => Action::PtySingleShift(idx, CharsetIndex::G2),
+            (b'O', None) => Action::PtySingleShift(idx, CharsetIndex::G3),
+            (b'c', None) => Action::PtyReset(idx),
+            (b'n', None) => Action::PtySetActiveCharset(idx, CharsetIndex::G2),
+            (b'o', None) => Action::PtySetActiveCharset(idx, CharsetIndex::G3),
+            (b'0', _) => configure_charset!(StandardCharset::SpecialCharacterAndLineDrawing),
+            (b'7', None) => Action::PtyCursorSavePosition(idx),
+            (b'8', Some(b'#')) => Action::PtyDectest(idx),
+            (b'8', None) => Action::PtyCursorRestorePosition(idx),
+            (b'=', None) => Action::PtySetKeypadApplicationMode(idx),
+            (b'>', None) => Action::PtyUnsetKeypadApplicationMode(idx),
+            _ => return,
+        };
+
+        self.send(action);
+    }
+}
+
+fn parse_number(input: &[u8]) -> Option<u8> {
+    str::from_utf8(input).ok()?.parse().ok()
+}
+
+fn decode_base64(input: &[u8]) -> Option<Vec<u8>> {
+    let mut data = Vec::with_capacity(input.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for &byte in input.iter().take_while(|b| **b != b'=') {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            data.push((bits >> count) as u8);
+        }
+    }
+    Some(data)
+}
+
+/// Parses an `rgb:rr/gg/bb` or `#rrggbb` color spec.
+fn parse_rgb_color(color: &[u8]) -> Option<(u8, u8, u8)> {
+    let color = str::from_utf8(color).ok().filter(|c| c.is_ascii())?;
+    let parse_channel = |channel: &str| -> Option<u8> {
+        // Scale channels of any width down to 8 bits.
+        let value = u32::from_str_radix(channel, 16).ok()?;
+        let max = (1u32 << (4 * channel.len() as u32)) - 1;
+        Some((value * 255 / max) as u8)
+    };
+
+    if color.starts_with("rgb:") {
+        let channels: Vec<&str> = color[4..].split('/').collect();
+        if channels.len() != 3 || channels.iter().any(|c| c.is_empty() || c.len() > 4) {
+            return None;
+        }
+        Some((
+            parse_channel(channels[0])?,
+            parse_channel(channels[1])?,
+            parse_channel(channels[2])?,
+        ))
+    } else if color.starts_with('#') && color.len() == 7 {
+        Some((
+            parse_channel(&color[1..3])?,
+            parse_channel(&color[3..5])?,
+            parse_channel(&color[5..7])?,
+        ))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::{Buffer, Style};
+    use crate::surface::Coord;
+
+    /// The size of the console the captures were taken from.
+    const CAPTURE_SIZE: Coord = Coord { x: 161, y: 14 };
+
+    fn replay(capture: &[u8]) -> Buffer {
+        let mut buffer = Buffer::new(CAPTURE_SIZE);
+        for action in parse(capture) {
+            buffer.perform(&action);
+        }
+        buffer
+    }
+
+    fn fg(buffer: &Buffer, y: usize, x: usize) -> Color {
+        buffer.rows()[y].cells()[x].style.fg
+    }
+
+    #[test]
+    fn osc_104_resets_palette() {
+        match parse(b"\x1b]104\x07").as_slice() {
+            [Action::PtyResetColors(PtyIndex(0))] => (),
+            actions => panic!("unexpected actions {:?}", actions),
+        }
+        match parse(b"\x1b]104;1;12\x07").as_slice() {
+            [Action::PtyResetColor(_, 1), Action::PtyResetColor(_, 12)] => (),
+            actions => panic!("unexpected actions {:?}", actions),
+        }
+    }
+
+    #[test]
+    fn sgr_with_private_marker_is_ignored() {
+        // xterm's modifyOtherKeys, which vim sends, looks like SGR with a `>` in front.
+        assert!(parse(b"\x1b[>4;2m").is_empty());
+        let buffer = replay(b"\x1b[>4;2mx");
+        assert_eq!(buffer.rows()[0].cells()[0].style, Style::default());
+    }
+
+    #[test]
+    fn non_ascii_colors_are_ignored() {
+        assert!(parse("\x1b]4;1;#a\u{e9}123\x07".as_bytes()).is_empty());
+        assert_eq!(parse_rgb_color("#a\u{e9}123".as_bytes()), None);
+        assert_eq!(parse_rgb_color("rgb:\u{e9}/00/00".as_bytes()), None);
+        assert_eq!(parse_rgb_color(b"#ff8000"), Some((255, 128, 0)));
+        assert_eq!(parse_rgb_color(b"rgb:ffff/0/80"), Some((255, 0, 128)));
+    }
+
+    #[test]
+    fn cursor_save_and_restore_need_no_intermediates() {
+        match parse(b"\x1b[s\x1b[u").as_slice() {
+            [Action::PtyCursorSavePosition(_), Action::PtyCursorRestorePosition(_)] => (),
+            actions => panic!("unexpected actions {:?}", actions),
+        }
+        // The kitty keyboard protocol and private modes use the same final characters.
+        assert!(parse(b"\x1b[>1u\x1b[<u\x1b[?u\x1b[?1s").is_empty());
+    }
+
+    #[test]
+    fn powershell_prompt() {
+        let buffer = replay(include_bytes!("../tests/captures/powershell_prompt.txt"));
+        assert_eq!(
+            buffer.title(),
+            Some("C:\\WINDOWS\\System32\\WindowsPowerShell\\v1.0\\powershell.exe")
+        );
+        assert_eq!(buffer.rows()[0].text(), "Windows PowerShell");
+        assert_eq!(
+            buffer.rows()[1].text(),
+            "Copyright (C) Microsoft Corporation. All rights reserved."
+        );
+        assert_eq!(buffer.rows()[2].text(), "");
+        assert_eq!(
+            buffer.rows()[3].text(),
+            "Loading personal and system profiles took 1359ms."
+        );
+        assert_eq!(buffer.rows()[4].text(), "C:\\");
+        assert_eq!(buffer.rows()[5].text(), "\u{276f}");
+        assert_eq!(fg(&buffer, 4, 0), Color::Named(NamedColor::BrightCyan));
+        assert_eq!(fg(&buffer, 5, 0), Color::Named(NamedColor::BrightGreen));
+        assert_eq!(buffer.rows()[0].cells()[0].style, Style::default());
+        assert_eq!(*buffer.cursor(), Coord { x: 1, y: 5 });
+        assert!(buffer.mode(Mode::ShowCursor));
+    }
+
+    #[test]
+    fn powershell_error() {
+        let buffer = replay(include_bytes!("../tests/captures/powershell_error.txt"));
+        assert_eq!(buffer.rows()[5].text(), "\u{276f} hello world");
+        assert_eq!(fg(&buffer, 5, 2), Color::Named(NamedColor::BrightYellow));
+        assert_eq!(fg(&buffer, 5, 8), Color::Named(NamedColor::Foreground));
+        assert!(buffer.rows()[6]
+            .text()
+            .starts_with("hello : The term 'hello' is not recognized"));
+        assert_eq!(fg(&buffer, 6, 0), Color::Named(NamedColor::BrightRed));
+        assert_eq!(buffer.rows()[9].text(), "+ hello world");
+        assert_eq!(buffer.rows()[10].text(), "+ ~~~~~");
+        assert_eq!(
+            buffer.rows()[12].text(),
+            "    + FullyQualifiedErrorId : CommandNotFoundException"
+        );
+        // Erased cells past the text keep the default background.
+        assert_eq!(buffer.rows()[9].cells()[20].style, Style::default());
+        assert_eq!(*buffer.cursor(), Coord { x: 1, y: 13 });
+        assert!(buffer.scrollback().is_empty());
+    }
+
+    #[test]
+    fn powershell_session() {
+        let buffer = replay(include_bytes!("../tests/captures/powershell_session.txt"));
+        assert_eq!(buffer.rows()[0].text(), "C:\\");
+        assert_eq!(buffer.rows()[1].text(), "\u{276f} exit");
+        assert!(buffer.rows()[2..].iter().all(|row| row.text().is_empty()));
+        assert_eq!(fg(&buffer, 1, 2), Color::Named(NamedColor::BrightGreen));
+        assert_eq!(*buffer.cursor(), Coord { x: 0, y: 2 });
+    }
+}
@@ -0,0 +1,292 @@
+use crate::compositor::Menu;
+use crate::keys::{Key, KeyCode};
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+/// How many automatically named buffers are kept before the oldest is dropped.
+pub const BUFFER_LIMIT: usize = 50;
+
+/// Text kept for pasting, from copy mode, a console setting the clipboard or a file.
+pub struct PasteBuffer {
+    name: String,
+    data: Vec<u8>,
+    /// Named by conmux rather than the user, these are dropped once there are too many
+    automatic: bool,
+}
+
+impl PasteBuffer {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The start of the text on one line, with control characters escaped.
+    pub fn preview(&self, width: usize) -> String {
+        let text = String::from_utf8_lossy(&self.data);
+        let mut preview = String::new();
+        for c in text.chars() {
+            match c {
+                '\n' => preview.push_str("\\n"),
+                '\r' => preview.push_str("\\r"),
+                '\t' => preview.push_str("\\t"),
+                '\\' => preview.push_str("\\\\"),
+                c if c.is_control() => preview.push_str(&format!("\\x{:02x}", c as u32)),
+                c => preview.push(c),
+            }
+
+            if preview.chars().count() > width {
+                let cut: String = preview.chars().take(width.saturating_sub(3)).collect();
+                return cut + "...";
+            }
+        }
+        preview
+    }
+}
+
+/// Paste buffers, most recent first.
+pub struct PasteBuffers {
+    buffers: Vec<PasteBuffer>,
+    /// Number used to name the next automatic buffer
+    next: usize,
+}
+
+impl PasteBuffers {
+    pub fn new() -> PasteBuffers {
+        PasteBuffers {
+            buffers: Vec::new(),
+            next: 0,
+        }
+    }
+
+    /// Adds a buffer with a name of its own on top, returning the name.
+    pub fn add(&mut self, data: Vec<u8>) -> String {
+        let name = format!("buffer{}", self.next);
+        self.next += 1;
+        self.buffers.insert(
+            0,
+            PasteBuffer {
+                name: name.clone(),
+                data,
+                automatic: true,
+            },
+        );
+
+        let automatic = self.buffers.iter().filter(|b| b.automatic).count();
+        if automatic > BUFFER_LIMIT {
+            if let Some(oldest) = self.buffers.iter().rposition(|b| b.automatic) {
+                self.buffers.remove(oldest);
+            }
+        }
+        name
+    }
+
+    /// Puts a named buffer on top, replacing a buffer of the same name.
+    pub fn set(&mut self, name: &str, data: Vec<u8>) {
+        self.delete(name);
+        self.buffers.insert(
+            0,
+            PasteBuffer {
+                name: name.to_owned(),
+                data,
+                automatic: false,
+            },
+        );
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PasteBuffer> {
+        self.buffers.iter().find(|b| b.name == name)
+    }
+
+    /// The most recent buffer.
+    pub fn top(&self) -> Option<&PasteBuffer> {
+        self.buffers.first()
+    }
+
+    /// The named buffer, or the most recent one without a name.
+    pub fn find(&self, name: Option<&str>) -> Result<&PasteBuffer> {
+        let buffer = match name {
+            Some(name) => self.get(name),
+            None => self.top(),
+        };
+        buffer.ok_or_else(|| match name {
+            Some(name) => Error::new(ErrorKind::NotFound, format!("no buffer {}", name)),
+            None => Error::new(ErrorKind::NotFound, "no buffers"),
+        })
+    }
+
+    /// Removes a buffer, returning whether there was one by that name.
+    pub fn delete(&mut self, name: &str) -> bool {
+        let len = self.buffers.len();
+        self.buffers.retain(|b| b.name != name);
+        self.buffers.len() != len
+    }
+
+    pub fn list(&self) -> &[PasteBuffer] {
+        &self.buffers
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+
+    /// Writes a buffer to a file, the most recent one if no name is given.
+    pub fn save(&self, name: Option<&str>, path: &Path) -> Result<()> {
+        fs::write(path, self.find(name)?.data())
+    }
+
+    /// Reads a file into a buffer, returning the name of the buffer.
+    pub fn load(&mut self, path: &Path, name: Option<&str>) -> Result<String> {
+        let data = fs::read(path)?;
+        Ok(match name {
+            Some(name) => {
+                self.set(name, data);
+                name.to_owned()
+            }
+            None => self.add(data),
+        })
+    }
+}
+
+/// What to do after a key was pressed in the buffer chooser.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChooserAction {
+    Continue,
+    Exit,
+    /// Paste the named buffer and leave
+    Paste(String),
+}
+
+/// A list of paste buffers shown over the active pane to pick one from.
+pub struct BufferChooser {
+    selected: usize,
+}
+
+impl BufferChooser {
+    pub fn new() -> BufferChooser {
+        BufferChooser { selected: 0 }
+    }
+
+    pub fn key(&mut self, key: Key, buffers: &mut PasteBuffers) -> ChooserAction {
+        let count = buffers.list().len();
+        if count == 0 {
+            return ChooserAction::Exit;
+        }
+        self.selected = self.selected.min(count - 1);
+
+        match (key.code, key.modifiers.ctrl) {
+            (KeyCode::Up, _) | (KeyCode::Char('k'), false) | (KeyCode::Char('p'), true) => {
+                self.selected = self.selected.saturating_sub(1)
+            }
+            (KeyCode::Down, _) | (KeyCode::Char('j'), false) | (KeyCode::Char('n'), true) => {
+                self.selected = (self.selected + 1).min(count - 1)
+            }
+            (KeyCode::Home, _) | (KeyCode::Char('g'), false) => self.selected = 0,
+            (KeyCode::End, _) | (KeyCode::Char('G'), false) => self.selected = count - 1,
+            (KeyCode::Enter, _) => {
+                return ChooserAction::Paste(buffers.list()[self.selected].name().to_owned())
+            }
+            (KeyCode::Char('d'), false) | (KeyCode::Delete, _) => {
+                let name = buffers.list()[self.selected].name().to_owned();
+                buffers.delete(&name);
+                if buffers.is_empty() {
+                    return ChooserAction::Exit;
+                }
+                self.selected = self.selected.min(buffers.list().len() - 1);
+            }
+            (KeyCode::Char('q'), false) | (KeyCode::Escape, _) | (KeyCode::Char('g'), true) => {
+                return ChooserAction::Exit
+            }
+            _ => (),
+        }
+        ChooserAction::Continue
+    }
+
+    /// The list of buffers as drawn over a pane `width` columns wide.
+    pub fn menu(&self, buffers: &PasteBuffers, width: usize) -> Menu {
+        let items = buffers
+            .list()
+            .iter()
+            .map(|b| {
+                let label = format!("{}: {} bytes: ", b.name(), b.data().len());
+                let room = width.saturating_sub(label.chars().count() + 2);
+                format!("{}\"{}\"", label, b.preview(room))
+            })
+            .collect();
+        Menu {
+            items,
+            selected: self.selected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(buffers: &PasteBuffers) -> Vec<&str> {
+        buffers.list().iter().map(|b| b.name()).collect()
+    }
+
+    #[test]
+    fn only_automatic_buffers_are_limited() {
+        let mut buffers = PasteBuffers::new();
+        buffers.set("kept", b"named".to_vec());
+        for n in 0..BUFFER_LIMIT + 5 {
+            buffers.add(n.to_string().into_bytes());
+        }
+
+        assert_eq!(buffers.list().len(), BUFFER_LIMIT + 1);
+        assert_eq!(buffers.top().unwrap().name(), "buffer54");
+        assert!(buffers.get("buffer4").is_none());
+        assert!(buffers.get("buffer5").is_some());
+        assert_eq!(buffers.get("kept").unwrap().data(), b"named");
+        assert_eq!(names(&buffers).last(), Some(&"kept"));
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = std::env::temp_dir().join(format!("conmux-paste-{}", std::process::id()));
+        let mut buffers = PasteBuffers::new();
+        buffers.add(b"first".to_vec());
+        buffers.add(b"line\nwith \x1b bytes".to_vec());
+
+        buffers.save(None, &path).unwrap();
+        assert_eq!(buffers.load(&path, Some("copy")).unwrap(), "copy");
+        buffers.save(Some("buffer0"), &path).unwrap();
+        assert_eq!(buffers.load(&path, None).unwrap(), "buffer2");
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(buffers.get("copy").unwrap().data(), b"line\nwith \x1b bytes");
+        assert_eq!(buffers.get("buffer2").unwrap().data(), b"first");
+        assert!(buffers.save(Some("missing"), &path).is_err());
+    }
+
+    #[test]
+    fn chooser_lists_most_recent_first() {
+        let mut buffers = PasteBuffers::new();
+        buffers.add(b"one".to_vec());
+        buffers.set("named", b"two".to_vec());
+        buffers.add(b"three".to_vec());
+        assert_eq!(names(&buffers), ["buffer1", "named", "buffer0"]);
+
+        let mut chooser = BufferChooser::new();
+        let menu = chooser.menu(&buffers, 80);
+        assert_eq!(menu.items[0], "buffer1: 5 bytes: \"three\"");
+        assert_eq!(menu.selected, 0);
+
+        let key = |code| Key::new(code);
+        assert_eq!(chooser.key(key(KeyCode::Char('j')), &mut buffers), ChooserAction::Continue);
+        assert_eq!(chooser.key(key(KeyCode::Char('d')), &mut buffers), ChooserAction::Continue);
+        assert_eq!(names(&buffers), ["buffer1", "buffer0"]);
+        assert_eq!(
+            chooser.key(key(KeyCode::Enter), &mut buffers),
+            ChooserAction::Paste(String::from("buffer0"))
+        );
+        assert_eq!(chooser.key(key(KeyCode::Char('q')), &mut buffers), ChooserAction::Exit);
+    }
+}
@@ -1,3 +1,4 @@
+use crate::buffer::Buffer;
 use crate::surface::Coord;
 use std::convert::{AsMut, AsRef};
 use std::io::{Read, Result, Write};
@@ -11,7 +12,7 @@ pub trait PseudoConsole<T>: Send + Sync
 where
     T: 'static,
 {
-    type Reader: Read + Send + Sync + Clone + 'static;
+    type Reader: Read + Send + Sync + 'static;
     type Writer: Write + Send + Sync + 'static;
     type KeepAlive: KeepAlive + 'static;
 
@@ -19,16 +20,19 @@ where
     fn resize(&mut self, coord: &Coord) -> Result<&Coord>;
     fn start_shell(&mut self) -> Result<()>;
     fn writer(&mut self) -> &mut Self::Writer;
-    fn reader(&self) -> &Self::Reader;
+    /// A new handle to read the output of the console from.
+    fn reader(&self) -> Result<Self::Reader>;
     fn keep_alive(&self) -> Self::KeepAlive;
 }
 
+/// A pseudoconsole along with the screen buffer its output is interpreted into.
 pub struct BufferedPseudoConsole<T>
 where
     T: PseudoConsole<T> + 'static,
     T: 'static,
 {
     console: T,
+    buffer: Buffer,
 }
 
 impl<T> BufferedPseudoConsole<T>
@@ -37,7 +41,22 @@ where
     T: 'static,
 {
     pub fn new(console: T) -> BufferedPseudoConsole<T> {
-        BufferedPseudoConsole { console }
+        let buffer = Buffer::new(*console.dimensions());
+        BufferedPseudoConsole { console, buffer }
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn buffer_mut(&mut self) -> &mut Buffer {
+        &mut self.buffer
+    }
+
+    /// Resizes both the console and its buffer.
+    pub fn resize(&mut self, coord: &Coord) -> Result<&Coord> {
+        self.buffer.resize(*coord);
+        self.console.resize(coord)
     }
 }
 
@@ -0,0 +1,245 @@
+use crate::buffer::Buffer;
+use crate::copy::Point;
+
+use regex::Regex;
+use std::io::{Error, ErrorKind, Result};
+
+/// What a search looks for.
+pub enum Pattern {
+    Text(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// Compiles a query typed at the search prompt.
+    pub fn new(query: &str, regex: bool) -> Result<Pattern> {
+        if !regex {
+            return Ok(Pattern::Text(query.to_owned()));
+        }
+        Regex::new(query)
+            .map(Pattern::Regex)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))
+    }
+
+    /// Byte ranges of the matches in `text`, empty matches are left out.
+    fn find(&self, text: &str) -> Vec<(usize, usize)> {
+        match self {
+            Pattern::Text(query) if query.is_empty() => Vec::new(),
+            Pattern::Text(query) => text
+                .match_indices(query.as_str())
+                .map(|(start, m)| (start, start + m.len()))
+                .collect(),
+            Pattern::Regex(regex) => regex
+                .find_iter(text)
+                .filter(|m| m.start() < m.end())
+                .map(|m| (m.start(), m.end()))
+                .collect(),
+        }
+    }
+}
+
+/// The cells a match covers, from `start` to `end` inclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub start: Point,
+    pub end: Point,
+}
+
+impl Match {
+    pub fn contains(&self, point: Point) -> bool {
+        self.start <= point && point <= self.end
+    }
+}
+
+/// Finds every match in the scrollback and screen of a buffer, in order.
+///
+/// Rows the console wrapped are searched as the one line they were written as, so matches
+/// can run from one row onto the next.
+pub fn find_matches(buffer: &Buffer, pattern: &Pattern) -> Vec<Match> {
+    let first = buffer.first_line();
+    let mut matches = Vec::new();
+
+    let mut text = String::new();
+    // Where the text of each cell starts in `text`, along with the cell
+    let mut cells: Vec<(usize, Point)> = Vec::new();
+
+    for n in 0..buffer.line_count() {
+        let row = match buffer.line(n) {
+            Some(row) => row,
+            None => continue,
+        };
+
+        // Blanks at the end of a line are padding, unless the line goes on in the next row.
+        let len = if row.wrapped() {
+            row.cells().len()
+        } else {
+            row.cells().iter().rposition(|c| !c.is_empty()).map_or(0, |i| i + 1)
+        };
+
+        // Spacers have no text of their own, so a match never starts or ends on one.
+        for (column, cell) in row.cells()[..len].iter().enumerate() {
+            if cell.is_spacer() {
+                continue;
+            }
+            cells.push((text.len(), Point { line: first + n, column }));
+            text.push_str(&cell.text());
+        }
+
+        if row.wrapped() {
+            continue;
+        }
+
+        for (start, end) in pattern.find(&text) {
+            let cell_at = |offset: usize| {
+                let idx = match cells.binary_search_by_key(&offset, |(o, _)| *o) {
+                    Ok(idx) => idx,
+                    Err(idx) => idx - 1,
+                };
+                cells[idx].1
+            };
+            matches.push(Match {
+                start: cell_at(start),
+                end: cell_at(end - 1),
+            });
+        }
+        text.clear();
+        cells.clear();
+    }
+    matches
+}
+
+/// A search that was run in copy mode, kept to jump between its matches and highlight them.
+pub struct Search {
+    pub query: String,
+    pub forward: bool,
+    matches: Vec<Match>,
+    /// The match the cursor was last moved to
+    current: Option<usize>,
+}
+
+impl Search {
+    pub fn new(query: String, forward: bool, matches: Vec<Match>) -> Search {
+        Search {
+            query,
+            forward,
+            matches,
+            current: None,
+        }
+    }
+
+    pub fn matches(&self) -> &[Match] {
+        &self.matches
+    }
+
+    pub fn current(&self) -> Option<Match> {
+        self.matches.get(self.current?).cloned()
+    }
+
+    /// Index of the match the cursor was last moved to.
+    pub fn position(&self) -> Option<usize> {
+        self.current
+    }
+
+    /// Matches that touch the lines from `first` up to `last`.
+    pub fn matches_between(&self, first: usize, last: usize) -> &[Match] {
+        let start = self.matches.partition_point(|m| m.end.line < first);
+        let end = self.matches.partition_point(|m| m.start.line <= last);
+        &self.matches[start..end.max(start)]
+    }
+
+    /// Moves to the next match after `from` in the search direction, or before it if `reverse`,
+    /// wrapping around the ends of the history.
+    pub fn next(&mut self, from: Point, reverse: bool) -> Option<Match> {
+        if self.matches.is_empty() {
+            return None;
+        }
+
+        let idx = if self.forward != reverse {
+            self.matches
+                .iter()
+                .position(|m| m.start > from)
+                .unwrap_or(0)
+        } else {
+            self.matches
+                .iter()
+                .rposition(|m| m.start < from)
+                .unwrap_or(self.matches.len() - 1)
+        };
+        self.current = Some(idx);
+        Some(self.matches[idx])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::parse;
+    use crate::surface::Coord;
+
+    fn buffer(width: usize, rows: usize, text: &[u8]) -> Buffer {
+        let mut buffer = Buffer::new(Coord { x: width, y: rows });
+        for action in parse(text) {
+            buffer.perform(&action);
+        }
+        buffer
+    }
+
+    fn at(line: usize, column: usize) -> Point {
+        Point { line, column }
+    }
+
+    fn starts(matches: &[Match]) -> Vec<Point> {
+        matches.iter().map(|m| m.start).collect()
+    }
+
+    #[test]
+    fn text_and_regex_patterns() {
+        let buffer = buffer(20, 2, b"foo f.o fao");
+
+        let text = Pattern::new("f.o", false).unwrap();
+        assert_eq!(starts(&find_matches(&buffer, &text)), [at(0, 4)]);
+
+        let regex = Pattern::new("f.o", true).unwrap();
+        let matches = find_matches(&buffer, &regex);
+        assert_eq!(starts(&matches), [at(0, 0), at(0, 4), at(0, 8)]);
+        assert_eq!(matches[2].end, at(0, 10));
+
+        assert!(find_matches(&buffer, &Pattern::new("", false).unwrap()).is_empty());
+        assert!(find_matches(&buffer, &Pattern::new("x*", true).unwrap()).is_empty());
+        let err = Pattern::new("(", true).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn matches_run_across_wrapped_rows() {
+        let buffer = buffer(5, 3, b"abcdefgh\r\nde");
+        let pattern = Pattern::new("def", false).unwrap();
+        let matches = find_matches(&buffer, &pattern);
+        assert_eq!(
+            matches,
+            [Match {
+                start: at(0, 3),
+                end: at(1, 0),
+            }]
+        );
+        assert!(matches[0].contains(at(0, 4)));
+        assert!(!matches[0].contains(at(1, 1)));
+    }
+
+    #[test]
+    fn next_wraps_around_the_history() {
+        let buffer = buffer(10, 3, b"x\r\n x\r\n  x");
+        let matches = find_matches(&buffer, &Pattern::new("x", false).unwrap());
+
+        let mut search = Search::new(String::from("x"), true, matches);
+        assert_eq!(search.next(at(0, 0), false).unwrap().start, at(1, 1));
+        assert_eq!(search.next(at(2, 2), false).unwrap().start, at(0, 0));
+        assert_eq!(search.position(), Some(0));
+        assert_eq!(search.next(at(0, 0), true).unwrap().start, at(2, 2));
+
+        let mut search = Search::new(String::from("x"), false, search.matches().to_vec());
+        assert_eq!(search.next(at(1, 1), false).unwrap().start, at(0, 0));
+        assert_eq!(search.next(at(0, 0), false).unwrap().start, at(2, 2));
+        assert_eq!(search.next(at(2, 2), true).unwrap().start, at(0, 0));
+    }
+}
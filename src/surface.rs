@@ -1,7 +1,7 @@
 use terminal_size::{terminal_size, Height, Width};
 use std::clone::Clone;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Coord {
     pub x: usize,
     pub y: usize,
@@ -22,6 +22,26 @@ impl From<&Coord> for Coord {
     }
 }
 
+/// A region of the host surface, in cells.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub origin: Coord,
+    pub size: Coord,
+}
+
+impl Rect {
+    pub fn new(origin: Coord, size: Coord) -> Rect {
+        Rect { origin, size }
+    }
+
+    pub fn contains(&self, point: &Coord) -> bool {
+        point.x >= self.origin.x
+            && point.y >= self.origin.y
+            && point.x < self.origin.x + self.size.x
+            && point.y < self.origin.y + self.size.y
+    }
+}
+
 pub struct Surface {
     pub dimensions: Coord,
 }
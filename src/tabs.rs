@@ -0,0 +1,103 @@
+use crate::layout::{Layout, Orientation};
+use crate::surface::Rect;
+
+/// A window of panes, only the panes of the active tab are on screen.
+///
+/// Consoles in inactive tabs keep running and buffering their output.
+pub struct Tab {
+    name: Option<String>,
+    layout: Layout,
+    active_console: usize,
+    zoomed: bool,
+}
+
+impl Tab {
+    pub fn new(console: usize) -> Tab {
+        Tab {
+            name: None,
+            layout: Layout::new(console),
+            active_console: console,
+            zoomed: false,
+        }
+    }
+
+    /// The name given with `rename`, if any.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(|n| n.as_str())
+    }
+
+    /// Names the tab, an empty name goes back to naming it after its active pane.
+    pub fn rename(&mut self, name: impl Into<String>) {
+        let name = name.into();
+        self.name = if name.is_empty() { None } else { Some(name) };
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    pub fn layout_mut(&mut self) -> &mut Layout {
+        &mut self.layout
+    }
+
+    pub fn active_console(&self) -> usize {
+        self.active_console
+    }
+
+    pub fn contains(&self, console: usize) -> bool {
+        self.layout.contains(console)
+    }
+
+    /// Focuses a pane of this tab, leaving zoom if it was hidden by it.
+    pub fn set_active_console(&mut self, console: usize) -> bool {
+        if !self.contains(console) {
+            return false;
+        }
+        if console != self.active_console {
+            self.zoomed = false;
+        }
+        self.active_console = console;
+        true
+    }
+
+    pub fn zoomed(&self) -> bool {
+        self.zoomed
+    }
+
+    /// Zooms the active pane if there is more than one pane, or unzooms it.
+    pub fn toggle_zoom(&mut self) {
+        self.zoomed = !self.zoomed && self.layout.leaves().len() > 1;
+    }
+
+    /// Splits the active pane, placing and focusing `console` in the new half.
+    pub fn split(&mut self, console: usize, orientation: Orientation) {
+        self.zoomed = false;
+        self.layout.split(self.active_console, console, orientation);
+        self.active_console = console;
+    }
+
+    /// Removes the pane of `console`, returning `false` if the tab has no panes left.
+    pub fn close(&mut self, console: usize) -> bool {
+        let layout = self.layout.clone().close(console);
+        match layout {
+            Some(layout) => {
+                self.layout = layout;
+                self.zoomed = false;
+                if self.active_console == console {
+                    self.active_console = self.layout.leaves()[0];
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The rectangle of every visible pane of the tab within `area`.
+    pub fn rects(&self, area: Rect) -> Vec<(usize, Rect)> {
+        if self.zoomed {
+            vec![(self.active_console, area)]
+        } else {
+            self.layout.rects(area)
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use libc::{self, termios, STDIN_FILENO};
+use std::io::{stdout, Error, Result, Write};
+use std::mem;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref ORIGINAL_MODE: Mutex<Option<termios>> = Mutex::new(None);
+}
+
+pub struct ConsoleEnabledToken;
+
+pub fn enable_console() -> Result<ConsoleEnabledToken> {
+    let mut console_in_mode: termios = unsafe { mem::zeroed() };
+
+    let result = unsafe { libc::tcgetattr(STDIN_FILENO, &mut console_in_mode) };
+
+    if result < 0 {
+        eprintln!("get terminal attributes error for input");
+        return Err(Error::last_os_error());
+    }
+
+    *ORIGINAL_MODE.lock().unwrap() = Some(console_in_mode);
+
+    // Disable echo, line input and signal processing so every key reaches the multiplexer.
+    let mut raw_mode = console_in_mode;
+    unsafe { libc::cfmakeraw(&mut raw_mode) };
+
+    let result = unsafe { libc::tcsetattr(STDIN_FILENO, libc::TCSANOW, &raw_mode) };
+
+    if result < 0 {
+        eprintln!("set terminal attributes error for raw input");
+        return Err(Error::last_os_error());
+    }
+    // Report mouse buttons and all motion, in the SGR encoding so large surfaces work,
+    // bracket pastes so they can be told apart from typing, and report focus changes.
+    print!("\x1b[?1049h\x1b[?1003h\x1b[?1006h\x1b[?2004h\x1b[?1004h");
+    stdout().flush()?;
+    Ok(ConsoleEnabledToken)
+}
+
+pub fn restore_console() {
+    if let Some(mode) = ORIGINAL_MODE.lock().unwrap().take() {
+        unsafe {
+            libc::tcsetattr(STDIN_FILENO, libc::TCSANOW, &mode);
+        }
+    }
+    println!("\x1b[?1004l\x1b[?2004l\x1b[?1006l\x1b[?1003l\x1b[?1049l");
+}
@@ -0,0 +1,280 @@
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::ptr::null_mut;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc::{self, pid_t, winsize};
+
+use crate::pty::{KeepAlive, PseudoConsole};
+use crate::surface::Coord;
+
+/// How long a shell gets to exit after a hangup before it is killed.
+const HANGUP_GRACE: Duration = Duration::from_millis(100);
+
+impl From<Coord> for winsize {
+    fn from(coord: Coord) -> winsize {
+        winsize {
+            ws_col: coord.x as _,
+            ws_row: coord.y as _,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        }
+    }
+}
+
+pub struct UnixPtyReader {
+    f_in: File,
+}
+
+pub struct UnixPtyWriter {
+    f_out: File,
+}
+
+impl Read for UnixPtyReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.f_in.read(buf)
+    }
+}
+
+impl UnixPtyReader {
+    /// Another reader for the same console, which fails if no more descriptors can be opened.
+    pub fn try_clone(&self) -> Result<UnixPtyReader> {
+        Ok(UnixPtyReader {
+            f_in: self.f_in.try_clone()?,
+        })
+    }
+}
+
+impl Write for UnixPtyWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.f_out.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.f_out.flush()
+    }
+}
+
+pub struct UnixPty {
+    master: File,
+    slave: Option<File>,
+    pid: pid_t,
+    size: Coord,
+    shell: String,
+    pwd: Option<PathBuf>,
+    pub pipes: (UnixPtyReader, UnixPtyWriter),
+}
+
+impl UnixPty {
+    pub fn new(
+        coord: impl Into<Coord>,
+        shell: impl Into<String>,
+        pwd: Option<&Path>,
+    ) -> Result<UnixPty> {
+        let coord = coord.into();
+        let (master, slave) = UnixPty::open_pseudo_console(coord)?;
+        let f_in = master.try_clone()?;
+        let f_out = master.try_clone()?;
+        Ok(UnixPty {
+            master,
+            slave: Some(slave),
+            pid: 0,
+            shell: shell.into(),
+            size: coord,
+            pwd: pwd.map(|p| p.to_owned()),
+            pipes: (UnixPtyReader { f_in }, UnixPtyWriter { f_out }),
+        })
+    }
+
+    pub fn start_shell(&mut self) -> Result<()> {
+        // The slave end is handed to the shell as its controlling terminal, we only
+        // keep the master end around once the child has been spawned.
+        let slave = self
+            .slave
+            .take()
+            .ok_or_else(|| Error::new(ErrorKind::Other, "Shell was already started"))?;
+        let slave_fd = slave.as_raw_fd();
+
+        let mut command = Command::new(&self.shell);
+        command
+            .stdin(unsafe { Stdio::from_raw_fd(libc::dup(slave_fd)) })
+            .stdout(unsafe { Stdio::from_raw_fd(libc::dup(slave_fd)) })
+            .stderr(unsafe { Stdio::from_raw_fd(libc::dup(slave_fd)) })
+            .env("TERM", "xterm-256color");
+
+        if let Some(pwd) = &self.pwd {
+            command.current_dir(pwd);
+        }
+
+        unsafe {
+            command.pre_exec(move || {
+                // Start a new session so the slave can become the controlling terminal.
+                if libc::setsid() < 0 {
+                    return Err(Error::last_os_error());
+                }
+
+                if libc::ioctl(slave_fd, libc::TIOCSCTTY as _, 0) < 0 {
+                    return Err(Error::last_os_error());
+                }
+
+                Ok(())
+            });
+        }
+
+        let child = command.spawn()?;
+        self.pid = child.id() as pid_t;
+        Ok(())
+    }
+
+    fn open_pseudo_console(coord: impl Into<Coord>) -> Result<(File, File)> {
+        let mut master: RawFd = -1;
+        let mut slave: RawFd = -1;
+        let mut size: winsize = coord.into().into();
+
+        let result = unsafe { libc::openpty(&mut master, &mut slave, null_mut(), null_mut(), &mut size) };
+
+        if result < 0 {
+            Err(Error::last_os_error())
+        } else {
+            // immediately consume the descriptors as rust File handles.
+            unsafe {
+                libc::fcntl(master, libc::F_SETFD, libc::FD_CLOEXEC);
+                libc::fcntl(slave, libc::F_SETFD, libc::FD_CLOEXEC);
+                Ok((File::from_raw_fd(master), File::from_raw_fd(slave)))
+            }
+        }
+    }
+
+    fn resize_pseudo_console(&mut self, coord: impl Into<Coord>) -> Result<()> {
+        let coord = coord.into();
+        let size: winsize = coord.into();
+        let result = unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) };
+        if result < 0 {
+            Err(Error::last_os_error())
+        } else {
+            self.size = coord;
+            Ok(())
+        }
+    }
+}
+
+impl Read for UnixPty {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.pipes.0.read(buf)
+    }
+}
+
+impl Write for UnixPty {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.pipes.1.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.pipes.1.flush()
+    }
+}
+
+pub struct UnixPtyKeepAlive {
+    pid: pid_t,
+}
+
+impl KeepAlive for UnixPtyKeepAlive {
+    fn dead(&self) -> bool {
+        if self.pid == 0 {
+            return false;
+        }
+
+        let mut status = 0;
+        match unsafe { libc::waitpid(self.pid, &mut status, libc::WNOHANG) } {
+            // Child is still running
+            0 => false,
+            // Child has exited, or was already reaped by another keep alive.
+            _ => true,
+        }
+    }
+}
+
+impl Clone for UnixPtyKeepAlive {
+    fn clone(&self) -> UnixPtyKeepAlive {
+        UnixPtyKeepAlive { pid: self.pid }
+    }
+}
+
+impl PseudoConsole<UnixPty> for UnixPty {
+    type Reader = UnixPtyReader;
+    type Writer = UnixPtyWriter;
+    type KeepAlive = UnixPtyKeepAlive;
+
+    fn dimensions(&self) -> &Coord {
+        &self.size
+    }
+
+    fn resize(&mut self, coord: &Coord) -> Result<&Coord> {
+        self.resize_pseudo_console(coord)
+            .map(move |_| self.dimensions())
+    }
+
+    fn start_shell(&mut self) -> Result<()> {
+        self.start_shell()
+    }
+
+    fn writer(&mut self) -> &mut Self::Writer {
+        &mut self.pipes.1
+    }
+
+    fn reader(&self) -> Result<Self::Reader> {
+        self.pipes.0.try_clone()
+    }
+
+    fn keep_alive(&self) -> Self::KeepAlive {
+        UnixPtyKeepAlive { pid: self.pid }
+    }
+}
+
+impl Drop for UnixPty {
+    fn drop(&mut self) {
+        if self.pid == 0 || self.keep_alive().dead() {
+            return;
+        }
+
+        unsafe {
+            libc::kill(self.pid, libc::SIGHUP);
+        }
+
+        // Reap the shell so it doesn't stay behind as a zombie, killing it if it ignores the hangup.
+        let hung_up = Instant::now();
+        let mut status = 0;
+        while unsafe { libc::waitpid(self.pid, &mut status, libc::WNOHANG) } == 0 {
+            if hung_up.elapsed() >= HANGUP_GRACE {
+                unsafe {
+                    libc::kill(self.pid, libc::SIGKILL);
+                    libc::waitpid(self.pid, &mut status, 0);
+                }
+                return;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drop_reaps_the_shell() {
+        let mut pty = UnixPty::new(Coord { x: 80, y: 24 }, "/bin/sh", None).unwrap();
+        pty.start_shell().unwrap();
+        let pid = pty.pid;
+        drop(pty);
+
+        // Nothing is left to wait for once the shell was reaped.
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) }, -1);
+    }
+}
@@ -51,11 +51,13 @@ pub fn enable_console() -> Result<ConsoleEnabledToken> {
         eprintln!("set console mode error for echo input disable");
         return Err(Error::last_os_error());
     }
-    print!("\x1b[?1049h");
+    // Report mouse buttons and all motion, in the SGR encoding so large surfaces work,
+    // bracket pastes so they can be told apart from typing, and report focus changes.
+    print!("\x1b[?1049h\x1b[?1003h\x1b[?1006h\x1b[?2004h\x1b[?1004h");
     Ok(ConsoleEnabledToken)
 }
 
 pub fn restore_console() {
     // todo: restore console modes.
-    println!("\x1b[?1049l");
+    println!("\x1b[?1004l\x1b[?2004l\x1b[?1006l\x1b[?1003l\x1b[?1049l");
 }
\ No newline at end of file